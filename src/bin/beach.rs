@@ -0,0 +1,785 @@
+//! Runs commands in the same sandbox as the Ocean library, configured by
+//! flags and sandbox profiles.
+
+use beach::{
+    landlock::Ruleset, oci::Bundle, profile::SandboxProfile, seccomp::Filter,
+    Backend, CapSet, Capability, Cgroup, Chroot, Error, Exit, IoMax, Network,
+    Overlay, Resource,
+};
+use clap::{Args, Parser, Subcommand};
+use serde::de::{value, DeserializeOwned, IntoDeserializer};
+use std::{
+    ffi::OsString, os::unix::io::RawFd, path::PathBuf, process, time::Duration,
+};
+
+/// Exit status when the sandbox cannot be set up, as with `chroot(1)`.
+const EXIT_FAILURE: i32 = 125;
+
+/// Exit status when the program cannot be found.
+const EXIT_NOT_FOUND: i32 = 127;
+
+/// Exit status when the program times out, as with `timeout(1)`.
+const EXIT_TIMEOUT: i32 = 124;
+
+#[derive(Parser)]
+#[command(name = "beach", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs a program with a different root directory.
+    Run(Box<Run>),
+    /// Runs the process of an OCI runtime bundle.
+    Oci(Oci),
+}
+
+#[derive(Args)]
+struct Oci {
+    /// The bundle directory, containing `config.json`.
+    #[arg(value_name = "BUNDLE")]
+    bundle: PathBuf,
+
+    /// Fails instead of warning about unsupported fields.
+    #[arg(long)]
+    strict: bool,
+}
+
+#[derive(Args)]
+struct Run {
+    /// The root directory.
+    #[arg(long, value_name = "DIR")]
+    root: PathBuf,
+
+    /// Starts from a `.toml` or `.json` sandbox profile, which other flags
+    /// add to.
+    #[arg(long, value_name = "FILE")]
+    profile: Option<PathBuf>,
+
+    /// The mechanism used to enter the root directory.
+    #[arg(long, value_name = "native|pivot-root|coreutils")]
+    #[arg(value_parser = kebab::<Backend>)]
+    backend: Option<Backend>,
+
+    /// Runs in a new user namespace, without privileges on the host.
+    #[arg(long)]
+    unprivileged: bool,
+
+    /// The user to run as, optionally followed by the group.
+    #[arg(long, value_name = "USER[:GROUP]")]
+    user: Option<String>,
+
+    /// Supplementary groups.
+    #[arg(long, value_name = "GROUP,...", value_delimiter = ',')]
+    groups: Vec<String>,
+
+    /// Does not change the working directory to `/`.
+    #[arg(long)]
+    skip_chdir: bool,
+
+    /// The working directory within the root, instead of `/`.
+    #[arg(long, value_name = "DIR")]
+    current_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    mounts: Mounts,
+
+    #[command(flatten)]
+    security: Security,
+
+    #[command(flatten)]
+    namespaces: Namespaces,
+
+    #[command(flatten)]
+    cgroup: CgroupArgs,
+
+    #[command(flatten)]
+    landlock: Landlock,
+
+    #[command(flatten)]
+    env: Env,
+
+    /// Passes a descriptor to the program, as CHILD_FD if given.
+    #[arg(long, value_name = "FD[:CHILD_FD]", value_parser = pass_fd)]
+    pass_fd: Vec<(RawFd, RawFd)>,
+
+    /// Kills the program after SECS seconds.
+    #[arg(long, value_name = "SECS", value_parser = seconds)]
+    timeout: Option<Duration>,
+
+    /// The program and its arguments.
+    #[arg(required = true, last = true, value_name = "PROGRAM")]
+    program: Vec<OsString>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Mounts")]
+struct Mounts {
+    /// Mounts an overlay of read-only directories over the root, listed from
+    /// top to bottom. Changes are discarded unless `--overlay-upper` is used.
+    #[arg(long, value_name = "DIR")]
+    overlay_lower: Vec<PathBuf>,
+
+    /// Writes the overlay's changes to UPPER, using WORK as scratch space.
+    #[arg(long, value_name = "UPPER:WORK", value_parser = pair)]
+    #[arg(requires = "overlay_lower")]
+    overlay_upper: Option<(PathBuf, PathBuf)>,
+
+    /// Bind-mounts SOURCE on the host to TARGET within the root.
+    #[arg(long, value_name = "SOURCE[:TARGET]", value_parser = pair_or_same)]
+    bind: Vec<(PathBuf, PathBuf)>,
+
+    /// Bind-mounts SOURCE on the host to TARGET within the root, read-only.
+    #[arg(long, value_name = "SOURCE[:TARGET]", value_parser = pair_or_same)]
+    bind_ro: Vec<(PathBuf, PathBuf)>,
+
+    /// Mounts a `tmpfs` at TARGET, limited to SIZE bytes.
+    #[arg(long, value_name = "TARGET[:SIZE]", value_parser = tmpfs)]
+    tmpfs: Vec<(PathBuf, u64)>,
+
+    /// Mounts `/proc`.
+    #[arg(long)]
+    proc: bool,
+
+    /// Bind-mounts the host's `/sys`, read-only.
+    #[arg(long)]
+    sys: bool,
+
+    /// Mounts a minimal `/dev`.
+    #[arg(long)]
+    dev: bool,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Security")]
+struct Security {
+    /// Installs the default seccomp filter.
+    #[arg(long)]
+    seccomp: bool,
+
+    /// Makes a system call fail with `EPERM`.
+    #[arg(long, value_name = "SYSCALL")]
+    deny_syscall: Vec<String>,
+
+    /// Kills the program when it makes a system call.
+    #[arg(long, value_name = "SYSCALL")]
+    kill_syscall: Vec<String>,
+
+    /// Sets every capability set, such as `chown,net_bind_service`.
+    #[arg(long, value_name = "CAP,...", value_parser = caps)]
+    caps: Option<CapSet>,
+
+    /// Sets the bounding capability set.
+    #[arg(long, value_name = "CAP,...", value_parser = caps)]
+    bounding_caps: Option<CapSet>,
+
+    /// Sets the permitted capability set.
+    #[arg(long, value_name = "CAP,...", value_parser = caps)]
+    permitted_caps: Option<CapSet>,
+
+    /// Sets the effective capability set.
+    #[arg(long, value_name = "CAP,...", value_parser = caps)]
+    effective_caps: Option<CapSet>,
+
+    /// Sets the inheritable capability set.
+    #[arg(long, value_name = "CAP,...", value_parser = caps)]
+    inheritable_caps: Option<CapSet>,
+
+    /// Sets the ambient capability set.
+    #[arg(long, value_name = "CAP,...", value_parser = caps)]
+    ambient_caps: Option<CapSet>,
+
+    /// Sets `PR_SET_NO_NEW_PRIVS`.
+    #[arg(long)]
+    no_new_privs: bool,
+
+    /// Limits a resource, such as `no-file=1024`, `cpu=60:90` or
+    /// `core=0:unlimited`.
+    #[arg(long, value_name = "RESOURCE=SOFT[:HARD]", value_parser = rlimit)]
+    rlimit: Vec<(Resource, u64, u64)>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Namespaces")]
+struct Namespaces {
+    /// The network access of the program.
+    #[arg(long, value_name = "none|loopback|host")]
+    #[arg(value_parser = kebab::<Network>)]
+    network: Option<Network>,
+
+    /// Runs the program in a new PID namespace.
+    #[arg(long)]
+    pid_namespace: bool,
+
+    /// Runs the program in a new IPC namespace.
+    #[arg(long)]
+    ipc_namespace: bool,
+
+    /// Sets the hostname in a new UTS namespace.
+    #[arg(long)]
+    hostname: Option<OsString>,
+
+    /// Sets the NIS domain name in a new UTS namespace.
+    #[arg(long)]
+    domainname: Option<OsString>,
+
+    /// Runs the program in a new time namespace, with clocks offset by the
+    /// given seconds.
+    #[arg(long, value_name = "MONOTONIC:BOOTTIME", value_parser = time)]
+    time_offsets: Option<(Duration, Duration)>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Cgroup")]
+struct CgroupArgs {
+    /// Creates the cgroup beneath DIR instead of the current cgroup. Limits
+    /// require a delegated DIR that contains no processes.
+    #[arg(long, value_name = "DIR")]
+    cgroup_parent: Option<PathBuf>,
+
+    /// Limits memory, such as `512M`.
+    #[arg(long, value_name = "BYTES", value_parser = size)]
+    memory_max: Option<u64>,
+
+    /// Limits the number of processes.
+    #[arg(long, value_name = "N")]
+    pids_max: Option<u64>,
+
+    /// Limits CPU time to QUOTA per PERIOD, in microseconds.
+    #[arg(long, value_name = "QUOTA:PERIOD", value_parser = cpu_max)]
+    cpu_max: Option<(Duration, Duration)>,
+
+    /// Limits I/O to a device, such as `8:0,rbps=1M,wiops=100`.
+    #[arg(long, value_name = "MAJOR:MINOR,KEY=VALUE,...")]
+    #[arg(value_parser = io_max)]
+    io_max: Vec<IoMax>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Landlock")]
+struct Landlock {
+    /// Allows reading beneath a path.
+    #[arg(long, value_name = "PATH")]
+    landlock_ro: Vec<PathBuf>,
+
+    /// Allows reading and writing beneath a path.
+    #[arg(long, value_name = "PATH")]
+    landlock_rw: Vec<PathBuf>,
+
+    /// Allows reading and executing beneath a path.
+    #[arg(long, value_name = "PATH")]
+    landlock_exec: Vec<PathBuf>,
+
+    /// Fails if the kernel cannot restrict every access right.
+    #[arg(long)]
+    landlock_strict: bool,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Environment")]
+struct Env {
+    /// Sets an environment variable.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = env_var)]
+    env: Vec<(OsString, OsString)>,
+
+    /// Passes an environment variable through, if it is set.
+    #[arg(long, value_name = "KEY")]
+    pass_env: Vec<OsString>,
+
+    /// Passes the whole environment through.
+    #[arg(long)]
+    inherit_env: bool,
+}
+
+impl Run {
+    fn chroot(&self) -> Result<Chroot, String> {
+        let mut chroot = match &self.profile {
+            Some(path) => SandboxProfile::load(path)
+                .map_err(|error| error.to_string())?
+                .chroot(),
+            None => Chroot::new(),
+        };
+
+        if let Some(backend) = self.backend {
+            chroot = chroot.backend(backend);
+        }
+        if self.unprivileged {
+            chroot = chroot.unprivileged();
+        }
+        if let Some(user) = &self.user {
+            chroot = match user.split_once(':') {
+                Some((user, group)) => chroot.user_group(user, group),
+                None => chroot.user(user),
+            };
+        }
+        chroot = chroot.groups(&self.groups);
+        if self.skip_chdir {
+            chroot = chroot.skip_chdir();
+        }
+        if let Some(dir) = &self.current_dir {
+            chroot = chroot.current_dir(dir);
+        }
+
+        chroot = self.mounts.apply(chroot);
+        chroot = self.security.apply(chroot);
+        chroot = self.namespaces.apply(chroot);
+        if let Some(cgroup) = self.cgroup.cgroup() {
+            chroot = chroot.cgroup(cgroup);
+        }
+        chroot = self.landlock.apply(chroot);
+        chroot = self.env.apply(chroot);
+        for &(host_fd, child_fd) in &self.pass_fd {
+            chroot = chroot.pass_fd(host_fd, child_fd);
+        }
+        Ok(chroot)
+    }
+
+    fn run(&self) -> i32 {
+        let chroot = match self.chroot() {
+            Ok(chroot) => chroot,
+            Err(error) => {
+                eprintln!("beach: {}", error);
+                return EXIT_FAILURE;
+            }
+        };
+
+        let (program, args) = self.program.split_first().unwrap();
+        let command = chroot.try_command(&self.root, program).map(|mut c| {
+            c.args(args);
+            c
+        });
+        status(&chroot, command, self.timeout)
+    }
+}
+
+impl Oci {
+    fn run(&self) -> i32 {
+        let bundle = match Bundle::load(&self.bundle) {
+            Ok(bundle) => bundle,
+            Err(error) => {
+                eprintln!("beach: {}", error);
+                return EXIT_FAILURE;
+            }
+        };
+
+        for field in bundle.unsupported() {
+            eprintln!("beach: unsupported: {}", field);
+        }
+        if self.strict && !bundle.unsupported().is_empty() {
+            return EXIT_FAILURE;
+        }
+        status(bundle.chroot(), bundle.try_command(), None)
+    }
+}
+
+/// Runs `command`, built by `chroot`, and returns the exit status to exit
+/// with.
+fn status(
+    chroot: &Chroot,
+    command: Result<process::Command, Error>,
+    timeout: Option<Duration>,
+) -> i32 {
+    let outcome = command.and_then(|mut command| {
+        let mut child = chroot.spawn(&mut command)?;
+        Ok(match timeout {
+            Some(timeout) => child.wait_timeout(timeout)?,
+            None => child.wait()?,
+        })
+    });
+
+    let exit = match outcome {
+        Ok(outcome) => outcome.exit,
+        Err(error) => {
+            eprintln!("beach: {}", error);
+            return match error {
+                Error::ProgramNotFound(_) => EXIT_NOT_FOUND,
+                _ => EXIT_FAILURE,
+            };
+        }
+    };
+
+    let code = match exit {
+        Exit::Code(code) => return code,
+        Exit::Signal(signal) => return 128 + signal,
+        Exit::Seccomp => 128 + libc::SIGSYS,
+        Exit::OutOfMemory => 128 + libc::SIGKILL,
+        Exit::Timeout => EXIT_TIMEOUT,
+        _ => EXIT_FAILURE,
+    };
+    eprintln!("beach: program {}", exit);
+    code
+}
+
+impl Mounts {
+    fn apply(&self, mut chroot: Chroot) -> Chroot {
+        if !self.overlay_lower.is_empty() {
+            let mut overlay = Overlay::new(&self.overlay_lower);
+            if let Some((upper, work)) = &self.overlay_upper {
+                overlay = overlay.upper(upper, work);
+            }
+            chroot = chroot.overlay(overlay);
+        }
+
+        for (source, target) in &self.bind {
+            chroot = chroot.bind(source, target);
+        }
+        for (source, target) in &self.bind_ro {
+            chroot = chroot.bind_ro(source, target);
+        }
+        if self.proc {
+            chroot = chroot.mount_proc();
+        }
+        if self.sys {
+            chroot = chroot.mount_sys();
+        }
+        if self.dev {
+            chroot = chroot.minimal_dev();
+        }
+        for (target, size) in &self.tmpfs {
+            chroot = chroot.tmpfs(target, *size);
+        }
+        chroot
+    }
+}
+
+impl Security {
+    fn apply(&self, mut chroot: Chroot) -> Chroot {
+        if self.seccomp
+            || !self.deny_syscall.is_empty()
+            || !self.kill_syscall.is_empty()
+        {
+            let mut filter = if self.seccomp {
+                Filter::default_profile()
+            } else {
+                Filter::new(beach::seccomp::Action::Allow)
+            };
+            for syscall in &self.deny_syscall {
+                filter = filter.errno(syscall.as_str(), libc::EPERM);
+            }
+            for syscall in &self.kill_syscall {
+                filter = filter.kill(syscall.as_str());
+            }
+            chroot = chroot.seccomp(filter);
+        }
+
+        if let Some(set) = self.caps {
+            chroot = chroot.capabilities(set);
+        }
+        if let Some(set) = self.bounding_caps {
+            chroot = chroot.bounding_caps(set);
+        }
+        if let Some(set) = self.permitted_caps {
+            chroot = chroot.permitted_caps(set);
+        }
+        if let Some(set) = self.effective_caps {
+            chroot = chroot.effective_caps(set);
+        }
+        if let Some(set) = self.inheritable_caps {
+            chroot = chroot.inheritable_caps(set);
+        }
+        if let Some(set) = self.ambient_caps {
+            chroot = chroot.ambient_caps(set);
+        }
+        if self.no_new_privs {
+            chroot = chroot.no_new_privs();
+        }
+
+        for &(resource, soft, hard) in &self.rlimit {
+            chroot = chroot.rlimit(resource, soft, hard);
+        }
+        chroot
+    }
+}
+
+impl Namespaces {
+    fn apply(&self, mut chroot: Chroot) -> Chroot {
+        if let Some(network) = self.network {
+            chroot = chroot.network(network);
+        }
+        if self.pid_namespace {
+            chroot = chroot.pid_namespace();
+        }
+        if self.ipc_namespace {
+            chroot = chroot.ipc_namespace();
+        }
+        if let Some(hostname) = &self.hostname {
+            chroot = chroot.hostname(hostname);
+        }
+        if let Some(domainname) = &self.domainname {
+            chroot = chroot.domainname(domainname);
+        }
+        if let Some((monotonic, boottime)) = self.time_offsets {
+            chroot = chroot.time_namespace(monotonic, boottime);
+        }
+        chroot
+    }
+}
+
+impl CgroupArgs {
+    fn cgroup(&self) -> Option<Cgroup> {
+        if self.cgroup_parent.is_none()
+            && self.memory_max.is_none()
+            && self.pids_max.is_none()
+            && self.cpu_max.is_none()
+            && self.io_max.is_empty()
+        {
+            return None;
+        }
+
+        let mut cgroup = Cgroup::new();
+        if let Some(parent) = &self.cgroup_parent {
+            cgroup = cgroup.parent(parent);
+        }
+        if let Some(bytes) = self.memory_max {
+            cgroup = cgroup.memory_max(bytes);
+        }
+        if let Some(max) = self.pids_max {
+            cgroup = cgroup.pids_max(max);
+        }
+        if let Some((quota, period)) = self.cpu_max {
+            cgroup = cgroup.cpu_max(quota, period);
+        }
+        for limits in &self.io_max {
+            cgroup = cgroup.io_max(*limits);
+        }
+        Some(cgroup)
+    }
+}
+
+impl Landlock {
+    fn apply(&self, chroot: Chroot) -> Chroot {
+        if self.landlock_ro.is_empty()
+            && self.landlock_rw.is_empty()
+            && self.landlock_exec.is_empty()
+            && !self.landlock_strict
+        {
+            return chroot;
+        }
+
+        let mut ruleset = Ruleset::new();
+        for path in &self.landlock_ro {
+            ruleset = ruleset.read_only(path);
+        }
+        for path in &self.landlock_rw {
+            ruleset = ruleset.read_write(path);
+        }
+        for path in &self.landlock_exec {
+            ruleset = ruleset.execute(path);
+        }
+        if self.landlock_strict {
+            ruleset = ruleset.strict();
+        }
+        chroot.landlock(ruleset)
+    }
+}
+
+impl Env {
+    fn apply(&self, mut chroot: Chroot) -> Chroot {
+        if self.inherit_env {
+            chroot = chroot.inherit_env();
+        }
+        for key in &self.pass_env {
+            chroot = chroot.pass_env(key);
+        }
+        chroot.envs(self.env.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+/// Parses a value named as in sandbox profiles, such as `pivot-root`.
+fn kebab<T: DeserializeOwned>(s: &str) -> Result<T, String> {
+    let deserializer: value::StrDeserializer<value::Error> =
+        s.into_deserializer();
+    T::deserialize(deserializer).map_err(|error| error.to_string())
+}
+
+fn pair(s: &str) -> Result<(PathBuf, PathBuf), String> {
+    match s.split_once(':') {
+        Some((a, b)) => Ok((a.into(), b.into())),
+        None => Err(format!("expected a colon in {:?}", s)),
+    }
+}
+
+fn pair_or_same(s: &str) -> Result<(PathBuf, PathBuf), String> {
+    pair(s).or_else(|_| Ok((s.into(), s.into())))
+}
+
+/// Parses a number of bytes with an optional `K`, `M`, `G` or `T` suffix.
+fn size(s: &str) -> Result<u64, String> {
+    let (digits, shift) = match s.as_bytes().last() {
+        Some(b'K') | Some(b'k') => (&s[..s.len() - 1], 10),
+        Some(b'M') | Some(b'm') => (&s[..s.len() - 1], 20),
+        Some(b'G') | Some(b'g') => (&s[..s.len() - 1], 30),
+        Some(b'T') | Some(b't') => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", s))?;
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("size {:?} is too large", s))
+}
+
+fn tmpfs(s: &str) -> Result<(PathBuf, u64), String> {
+    match s.split_once(':') {
+        Some((target, bytes)) => Ok((target.into(), size(bytes)?)),
+        None => Ok((s.into(), 0)),
+    }
+}
+
+fn caps(s: &str) -> Result<CapSet, String> {
+    if s.eq_ignore_ascii_case("all") {
+        return Ok(CapSet::all());
+    }
+    s.split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| name.parse::<Capability>().map_err(|e| e.to_string()))
+        .collect()
+}
+
+fn rlimit(s: &str) -> Result<(Resource, u64, u64), String> {
+    let (resource, limits) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected RESOURCE=SOFT[:HARD], got {:?}", s))?;
+    let resource = kebab(resource)?;
+    let (soft, hard) = match limits.split_once(':') {
+        Some((soft, hard)) => (limit(soft)?, limit(hard)?),
+        None => (limit(limits)?, limit(limits)?),
+    };
+    Ok((resource, soft, hard))
+}
+
+/// Parses a resource limit, where `unlimited` or `infinity` is `u64::MAX`.
+fn limit(s: &str) -> Result<u64, String> {
+    if s.eq_ignore_ascii_case("unlimited") || s.eq_ignore_ascii_case("infinity")
+    {
+        return Ok(u64::MAX);
+    }
+    size(s)
+}
+
+fn time(s: &str) -> Result<(Duration, Duration), String> {
+    let secs = |s: &str| {
+        s.parse()
+            .map(Duration::from_secs)
+            .map_err(|_| format!("invalid number of seconds {:?}", s))
+    };
+    match s.split_once(':') {
+        Some((monotonic, boottime)) => Ok((secs(monotonic)?, secs(boottime)?)),
+        None => Ok((secs(s)?, secs(s)?)),
+    }
+}
+
+fn seconds(s: &str) -> Result<Duration, String> {
+    s.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("invalid number of seconds {:?}", s))
+}
+
+fn cpu_max(s: &str) -> Result<(Duration, Duration), String> {
+    let micros = |s: &str| {
+        s.parse()
+            .map(Duration::from_micros)
+            .map_err(|_| format!("invalid number of microseconds {:?}", s))
+    };
+    let (quota, period) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected QUOTA:PERIOD, got {:?}", s))?;
+    Ok((micros(quota)?, micros(period)?))
+}
+
+fn io_max(s: &str) -> Result<IoMax, String> {
+    let mut parts = s.split(',');
+    let device = parts.next().unwrap_or_default();
+    let number = |n: &str| {
+        n.parse()
+            .map_err(|_| format!("invalid device number {:?}", device))
+    };
+    let (major, minor) = device
+        .split_once(':')
+        .ok_or_else(|| format!("expected MAJOR:MINOR, got {:?}", device))?;
+    let mut limits = IoMax::new(number(major)?, number(minor)?);
+
+    for part in parts {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", part))?;
+        let value = size(value)?;
+        limits = match key {
+            "rbps" => limits.read_bps(value),
+            "wbps" => limits.write_bps(value),
+            "riops" => limits.read_iops(value),
+            "wiops" => limits.write_iops(value),
+            _ => return Err(format!("unknown I/O limit {:?}", key)),
+        };
+    }
+    Ok(limits)
+}
+
+fn env_var(s: &str) -> Result<(OsString, OsString), String> {
+    match s.split_once('=') {
+        Some((key, value)) => Ok((key.into(), value.into())),
+        None => Err(format!("expected KEY=VALUE, got {:?}", s)),
+    }
+}
+
+fn pass_fd(s: &str) -> Result<(RawFd, RawFd), String> {
+    let fd = |s: &str| {
+        s.parse()
+            .map_err(|_| format!("invalid file descriptor {:?}", s))
+    };
+    match s.split_once(':') {
+        Some((host, child)) => Ok((fd(host)?, fd(child)?)),
+        None => Ok((fd(s)?, fd(s)?)),
+    }
+}
+
+fn main() {
+    let code = match Cli::parse().command {
+        Command::Run(run) => run.run(),
+        Command::Oci(oci) => oci.run(),
+    };
+    process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_parsed() {
+        assert_eq!(size("4096"), Ok(4096));
+        assert_eq!(size("512k"), Ok(512 << 10));
+        assert_eq!(size("2M"), Ok(2 << 20));
+        assert_eq!(size("1G"), Ok(1 << 30));
+        assert_eq!(size("3t"), Ok(3 << 40));
+        assert!(size("").is_err());
+        assert!(size("M").is_err());
+        assert!(size("-1").is_err());
+        assert!(size("unlimited").is_err());
+        assert!(size("16777216T").is_err());
+    }
+
+    #[test]
+    fn rlimits_are_parsed() {
+        assert_eq!(rlimit("no-file=1024"), Ok((Resource::NoFile, 1024, 1024)));
+        assert_eq!(rlimit("cpu=60:90"), Ok((Resource::Cpu, 60, 90)));
+        assert_eq!(
+            rlimit("core=0:unlimited"),
+            Ok((Resource::Core, 0, u64::MAX))
+        );
+        assert_eq!(
+            rlimit("stack=Infinity"),
+            Ok((Resource::Stack, u64::MAX, u64::MAX))
+        );
+        assert_eq!(
+            rlimit("address-space=1G"),
+            Ok((Resource::AddressSpace, 1 << 30, 1 << 30))
+        );
+        assert!(rlimit("no-file").is_err());
+        assert!(rlimit("no-file=lots").is_err());
+        assert!(rlimit("no-such=1").is_err());
+    }
+}
@@ -0,0 +1,414 @@
+use std::{error, fmt, io, iter::FromIterator, str::FromStr};
+
+macro_rules! capabilities {
+    ($($(#[$doc:meta])* $variant:ident = $value:literal, $name:literal;)+) => {
+        /// A Linux [capability](http://man7.org/linux/man-pages/man7/capabilities.7.html).
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[non_exhaustive]
+        pub enum Capability {
+            $($(#[$doc])* $variant = $value,)+
+        }
+
+        impl Capability {
+            /// Every capability, in order.
+            pub const ALL: &'static [Capability] = &[$(Self::$variant,)+];
+
+            /// Returns the name of the capability, such as `CAP_CHOWN`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)+
+                }
+            }
+        }
+    };
+}
+
+capabilities! {
+    /// Make arbitrary changes to file UIDs and GIDs.
+    Chown = 0, "CAP_CHOWN";
+    /// Bypass file read, write and execute permission checks.
+    DacOverride = 1, "CAP_DAC_OVERRIDE";
+    /// Bypass file read and directory search permission checks.
+    DacReadSearch = 2, "CAP_DAC_READ_SEARCH";
+    /// Bypass permission checks that require the file's owner.
+    Fowner = 3, "CAP_FOWNER";
+    /// Keep set-user-ID and set-group-ID bits when modifying files.
+    Fsetid = 4, "CAP_FSETID";
+    /// Bypass permission checks for sending signals.
+    Kill = 5, "CAP_KILL";
+    /// Make arbitrary manipulations of process GIDs.
+    Setgid = 6, "CAP_SETGID";
+    /// Make arbitrary manipulations of process UIDs.
+    Setuid = 7, "CAP_SETUID";
+    /// Modify capability sets.
+    Setpcap = 8, "CAP_SETPCAP";
+    /// Set the immutable and append-only file attributes.
+    LinuxImmutable = 9, "CAP_LINUX_IMMUTABLE";
+    /// Bind sockets to ports below 1024.
+    NetBindService = 10, "CAP_NET_BIND_SERVICE";
+    /// Make socket broadcasts and listen to multicasts.
+    NetBroadcast = 11, "CAP_NET_BROADCAST";
+    /// Perform network administration.
+    NetAdmin = 12, "CAP_NET_ADMIN";
+    /// Use raw and packet sockets.
+    NetRaw = 13, "CAP_NET_RAW";
+    /// Lock memory.
+    IpcLock = 14, "CAP_IPC_LOCK";
+    /// Bypass permission checks for System V IPC objects.
+    IpcOwner = 15, "CAP_IPC_OWNER";
+    /// Load and unload kernel modules.
+    SysModule = 16, "CAP_SYS_MODULE";
+    /// Perform I/O port operations.
+    SysRawio = 17, "CAP_SYS_RAWIO";
+    /// Use `chroot(2)`.
+    SysChroot = 18, "CAP_SYS_CHROOT";
+    /// Trace arbitrary processes.
+    SysPtrace = 19, "CAP_SYS_PTRACE";
+    /// Use `acct(2)`.
+    SysPacct = 20, "CAP_SYS_PACCT";
+    /// Perform a range of system administration operations.
+    SysAdmin = 21, "CAP_SYS_ADMIN";
+    /// Use `reboot(2)` and `kexec_load(2)`.
+    SysBoot = 22, "CAP_SYS_BOOT";
+    /// Raise process priorities.
+    SysNice = 23, "CAP_SYS_NICE";
+    /// Override resource limits.
+    SysResource = 24, "CAP_SYS_RESOURCE";
+    /// Set the system clock.
+    SysTime = 25, "CAP_SYS_TIME";
+    /// Configure virtual terminals.
+    SysTtyConfig = 26, "CAP_SYS_TTY_CONFIG";
+    /// Create special files with `mknod(2)`.
+    Mknod = 27, "CAP_MKNOD";
+    /// Establish leases on arbitrary files.
+    Lease = 28, "CAP_LEASE";
+    /// Write records to the kernel auditing log.
+    AuditWrite = 29, "CAP_AUDIT_WRITE";
+    /// Configure kernel auditing.
+    AuditControl = 30, "CAP_AUDIT_CONTROL";
+    /// Set file capabilities.
+    Setfcap = 31, "CAP_SETFCAP";
+    /// Override Mandatory Access Control.
+    MacOverride = 32, "CAP_MAC_OVERRIDE";
+    /// Configure Mandatory Access Control.
+    MacAdmin = 33, "CAP_MAC_ADMIN";
+    /// Perform privileged `syslog(2)` operations.
+    Syslog = 34, "CAP_SYSLOG";
+    /// Trigger something that will wake up the system.
+    WakeAlarm = 35, "CAP_WAKE_ALARM";
+    /// Block system suspend.
+    BlockSuspend = 36, "CAP_BLOCK_SUSPEND";
+    /// Read the audit log.
+    AuditRead = 37, "CAP_AUDIT_READ";
+    /// Use performance monitoring.
+    Perfmon = 38, "CAP_PERFMON";
+    /// Use privileged BPF operations.
+    Bpf = 39, "CAP_BPF";
+    /// Checkpoint and restore processes.
+    CheckpointRestore = 40, "CAP_CHECKPOINT_RESTORE";
+}
+
+impl fmt::Display for Capability {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Capability {
+    type Err = ParseCapabilityError;
+
+    /// Parses a name such as `CAP_CHOWN`, ignoring case and the `CAP_`
+    /// prefix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.to_ascii_uppercase();
+        let name = upper.strip_prefix("CAP_").unwrap_or(&upper);
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|cap| &cap.name()[4..] == name)
+            .ok_or_else(|| ParseCapabilityError(s.to_owned()))
+    }
+}
+
+/// An error returned when parsing an unknown [`Capability`] name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseCapabilityError(String);
+
+impl fmt::Display for ParseCapabilityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown capability {:?}", self.0)
+    }
+}
+
+impl error::Error for ParseCapabilityError {}
+
+/// A set of [`Capability`] values.
+///
+/// # Examples
+///
+/// ```
+/// use beach::{CapSet, Capability};
+///
+/// let set: CapSet = [Capability::Chown, Capability::Fowner]
+///     .iter()
+///     .copied()
+///     .collect();
+///
+/// assert!(set.contains(Capability::Chown));
+/// assert!(!set.contains(Capability::SysAdmin));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CapSet(u64);
+
+impl CapSet {
+    /// Returns a set without any capabilities.
+    #[inline]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns a set of every known capability.
+    #[inline]
+    pub fn all() -> Self {
+        Capability::ALL.iter().copied().collect()
+    }
+
+    /// Returns whether `cap` is in the set.
+    #[inline]
+    pub fn contains(self, cap: Capability) -> bool {
+        self.0 & (1 << cap as u64) != 0
+    }
+
+    /// Adds `cap` to the set.
+    #[inline]
+    pub fn insert(&mut self, cap: Capability) {
+        self.0 |= 1 << cap as u64;
+    }
+
+    /// Removes `cap` from the set.
+    #[inline]
+    pub fn remove(&mut self, cap: Capability) {
+        self.0 &= !(1 << cap as u64);
+    }
+
+    /// Returns whether the set is empty.
+    #[inline]
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns an iterator over the capabilities in the set.
+    pub fn iter(self) -> impl Iterator<Item = Capability> {
+        Capability::ALL
+            .iter()
+            .copied()
+            .filter(move |&cap| self.contains(cap))
+    }
+
+    fn bits(self) -> u64 {
+        self.0
+    }
+}
+
+impl From<Capability> for CapSet {
+    #[inline]
+    fn from(cap: Capability) -> Self {
+        Self(1 << cap as u64)
+    }
+}
+
+impl FromIterator<Capability> for CapSet {
+    fn from_iter<I: IntoIterator<Item = Capability>>(iter: I) -> Self {
+        let mut set = Self::empty();
+        for cap in iter {
+            set.insert(cap);
+        }
+        set
+    }
+}
+
+/// The capability sets to apply to the child, each left unchanged if `None`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Caps {
+    pub bounding: Option<CapSet>,
+    pub permitted: Option<CapSet>,
+    pub effective: Option<CapSet>,
+    pub inheritable: Option<CapSet>,
+    pub ambient: Option<CapSet>,
+}
+
+impl Caps {
+    pub const fn new() -> Self {
+        Self {
+            bounding: None,
+            permitted: None,
+            effective: None,
+            inheritable: None,
+            ambient: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bounding.is_none()
+            && self.permitted.is_none()
+            && self.effective.is_none()
+            && self.inheritable.is_none()
+            && self.ambient.is_none()
+    }
+
+    /// Applied before credentials change, while `CAP_SETPCAP` is still held.
+    ///
+    /// Only async-signal-safe operations are used.
+    pub fn apply_before_setuid(&self) -> io::Result<()> {
+        if let Some(bounding) = self.bounding {
+            // Include capabilities unknown to us but known to the kernel.
+            for cap in 0..64 {
+                if bounding.bits() & (1 << cap) != 0 {
+                    continue;
+                }
+                let ret = unsafe {
+                    libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong)
+                };
+                if ret == -1 {
+                    let error = io::Error::last_os_error();
+                    if error.raw_os_error() == Some(libc::EINVAL) {
+                        break;
+                    }
+                    return Err(error);
+                }
+            }
+        }
+
+        // Keep the permitted set when switching away from root.
+        if self.permitted.is_some()
+            || self.effective.is_some()
+            || self.ambient.is_some()
+        {
+            let ret = unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1) };
+            if ret == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applied after credentials change.
+    ///
+    /// Only async-signal-safe operations are used.
+    pub fn apply_after_setuid(&self) -> io::Result<()> {
+        let inheritable = match (self.inheritable, self.ambient) {
+            // Ambient capabilities must also be inheritable.
+            (inheritable, Some(ambient)) => {
+                Some(CapSet(inheritable.unwrap_or_default().0 | ambient.0))
+            }
+            (inheritable, None) => inheritable,
+        };
+
+        if self.permitted.is_some()
+            || self.effective.is_some()
+            || inheritable.is_some()
+        {
+            let mut data = capget()?;
+            if let Some(set) = self.permitted {
+                set_words(&mut data, |d| &mut d.permitted, set);
+            }
+            if let Some(set) = self.effective {
+                set_words(&mut data, |d| &mut d.effective, set);
+            }
+            if let Some(set) = inheritable {
+                set_words(&mut data, |d| &mut d.inheritable, set);
+            }
+            capset(&data)?;
+        }
+
+        if let Some(ambient) = self.ambient {
+            let clear = libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong;
+            let ret =
+                unsafe { libc::prctl(libc::PR_CAP_AMBIENT, clear, 0, 0, 0) };
+            if ret == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            for cap in ambient.iter() {
+                let raise = libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong;
+                let ret = unsafe {
+                    libc::prctl(
+                        libc::PR_CAP_AMBIENT,
+                        raise,
+                        cap as libc::c_ulong,
+                        0,
+                        0,
+                    )
+                };
+                if ret == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn set_words<F>(data: &mut [CapData; 2], field: F, set: CapSet)
+where
+    F: Fn(&mut CapData) -> &mut u32,
+{
+    *field(&mut data[0]) = set.bits() as u32;
+    *field(&mut data[1]) = (set.bits() >> 32) as u32;
+}
+
+fn capget() -> io::Result<[CapData; 2]> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+
+    let ret = unsafe {
+        libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr())
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(data)
+}
+
+fn capset(data: &[CapData; 2]) -> io::Result<()> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+
+    let ret =
+        unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns whether the calling thread has `cap` in its effective set.
+pub(crate) fn has_effective(cap: Capability) -> io::Result<bool> {
+    let data = capget()?;
+    let cap = cap as u32;
+    let word = data[(cap / 32) as usize].effective;
+    Ok(word & (1 << (cap % 32)) != 0)
+}
@@ -0,0 +1,421 @@
+use crate::Error;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// A transient [cgroup v2](https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html)
+/// that limits and accounts for a sandboxed command and its descendants.
+///
+/// The cgroup given to [`Chroot::cgroup`](crate::Chroot::cgroup) serves as a
+/// template: [`Chroot::spawn`](crate::Chroot::spawn) creates a cgroup with a
+/// unique name and the same limits below [`parent`](Self::parent) for each
+/// command, which the child joins before anything else. It is available as
+/// [`Child::cgroup`](crate::Child::cgroup).
+///
+/// Controllers needed for the configured limits are enabled in the parent,
+/// which must therefore be writable and contain no processes of its own, as
+/// with a cgroup delegated by systemd's `Delegate=yes`. The default parent,
+/// the cgroup of the calling process, contains that process, so it only
+/// works without limits, for accounting. Limits require an explicit
+/// [`parent`](Self::parent); otherwise, spawning the command fails with
+/// [`Error::Cgroup`].
+///
+/// # Examples
+///
+/// ```
+/// # return;
+/// use beach::{Cgroup, Chroot};
+///
+/// let cgroup = Cgroup::new()
+///     .parent("/sys/fs/cgroup/sandbox.slice")
+///     .memory_max(1 << 30)
+///     .pids_max(256);
+///
+/// let chroot = Chroot::new().cgroup(cgroup);
+/// let mut command = chroot.try_command("/path/to/root", "make").unwrap();
+/// let mut child = chroot.spawn(&mut command).unwrap();
+/// child.wait().unwrap();
+///
+/// let stats = child.cgroup().unwrap().stats().unwrap();
+/// println!("{:?} of CPU time", stats.cpu_usage);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cgroup {
+    parent: Option<PathBuf>,
+    name: String,
+    memory_max: Option<u64>,
+    pids_max: Option<u64>,
+    cpu_max: Option<(Duration, Duration)>,
+    io_max: Vec<IoMax>,
+}
+
+/// Limits on a block device, used with [`Cgroup::io_max`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IoMax {
+    major: u32,
+    minor: u32,
+    read_bps: Option<u64>,
+    write_bps: Option<u64>,
+    read_iops: Option<u64>,
+    write_iops: Option<u64>,
+}
+
+impl IoMax {
+    /// Creates limits for the block device with the given numbers, without
+    /// limiting anything yet.
+    #[inline]
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self {
+            major,
+            minor,
+            read_bps: None,
+            write_bps: None,
+            read_iops: None,
+            write_iops: None,
+        }
+    }
+
+    /// Limits reads to `bps` bytes per second.
+    #[inline]
+    pub fn read_bps(mut self, bps: u64) -> Self {
+        self.read_bps = Some(bps);
+        self
+    }
+
+    /// Limits writes to `bps` bytes per second.
+    #[inline]
+    pub fn write_bps(mut self, bps: u64) -> Self {
+        self.write_bps = Some(bps);
+        self
+    }
+
+    /// Limits reads to `iops` operations per second.
+    #[inline]
+    pub fn read_iops(mut self, iops: u64) -> Self {
+        self.read_iops = Some(iops);
+        self
+    }
+
+    /// Limits writes to `iops` operations per second.
+    #[inline]
+    pub fn write_iops(mut self, iops: u64) -> Self {
+        self.write_iops = Some(iops);
+        self
+    }
+
+    /// Returns the line written to `io.max`.
+    fn line(&self) -> String {
+        let mut line = format!("{}:{}", self.major, self.minor);
+        for (key, value) in &[
+            ("rbps", self.read_bps),
+            ("wbps", self.write_bps),
+            ("riops", self.read_iops),
+            ("wiops", self.write_iops),
+        ] {
+            if let Some(value) = value {
+                line.push_str(&format!(" {}={}", key, value));
+            }
+        }
+        line
+    }
+}
+
+/// Resource usage of a [`Cgroup`], as returned by [`Cgroup::stats`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CgroupStats {
+    /// The total CPU time used.
+    pub cpu_usage: Duration,
+    /// The CPU time spent in user mode.
+    pub cpu_user: Duration,
+    /// The CPU time spent in kernel mode.
+    pub cpu_system: Duration,
+    /// The highest memory usage in bytes, if the memory controller is
+    /// enabled and the kernel reports it (Linux 5.19 and later).
+    pub memory_peak: Option<u64>,
+    /// The number of processes killed by the OOM killer, if the memory
+    /// controller is enabled.
+    pub oom_kills: Option<u64>,
+}
+
+impl Default for Cgroup {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cgroup {
+    /// Creates a cgroup with a unique name and no limits.
+    pub fn new() -> Self {
+        Self {
+            parent: None,
+            name: unique_name(),
+            memory_max: None,
+            pids_max: None,
+            cpu_max: None,
+            io_max: Vec::new(),
+        }
+    }
+
+    /// Creates the cgroup below `parent` instead of the cgroup of the calling
+    /// process, which is required for limits.
+    ///
+    /// `parent` must be writable and contain no processes.
+    pub fn parent<P: AsRef<Path>>(mut self, parent: P) -> Self {
+        self.parent = Some(parent.as_ref().to_owned());
+        self
+    }
+
+    /// Limits memory usage to `bytes`, beyond which the OOM killer is
+    /// invoked.
+    #[inline]
+    pub fn memory_max(mut self, bytes: u64) -> Self {
+        self.memory_max = Some(bytes);
+        self
+    }
+
+    /// Limits the number of processes and threads.
+    #[inline]
+    pub fn pids_max(mut self, max: u64) -> Self {
+        self.pids_max = Some(max);
+        self
+    }
+
+    /// Limits CPU usage to `quota` in each `period`.
+    ///
+    /// For example, a quota of twice the period allows using two CPUs.
+    #[inline]
+    pub fn cpu_max(mut self, quota: Duration, period: Duration) -> Self {
+        self.cpu_max = Some((quota, period));
+        self
+    }
+
+    /// Limits I/O on a block device.
+    #[inline]
+    pub fn io_max(mut self, limits: IoMax) -> Self {
+        self.io_max.push(limits);
+        self
+    }
+
+    /// Returns the directory of the cgroup.
+    ///
+    /// Only the cgroups of [`Child`](crate::Child)ren exist, so this and the
+    /// methods below are meant for those.
+    pub fn path(&self) -> io::Result<PathBuf> {
+        let parent = match &self.parent {
+            Some(parent) => parent.clone(),
+            None => current()?,
+        };
+        Ok(parent.join(&self.name))
+    }
+
+    /// Creates a cgroup with the same limits and a new unique name.
+    pub(crate) fn create_unique(&self) -> Result<Self, Error> {
+        let cgroup = Self {
+            name: unique_name(),
+            ..self.clone()
+        };
+        cgroup.create()?;
+        Ok(cgroup)
+    }
+
+    /// Creates the cgroup and applies its limits, returning its directory.
+    fn create(&self) -> Result<PathBuf, Error> {
+        let path = self.path()?;
+        let parent = path.parent().unwrap_or(&path);
+
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() {
+            controllers.push("memory");
+        }
+        if self.pids_max.is_some() {
+            controllers.push("pids");
+        }
+        if self.cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        if !self.io_max.is_empty() {
+            controllers.push("io");
+        }
+
+        // Controllers must be available in the parent to be enabled for its
+        // children.
+        let controllers_file = parent.join("cgroup.controllers");
+        let available = read(&controllers_file)?;
+        let subtree_control = parent.join("cgroup.subtree_control");
+        let enabled = read(&subtree_control)?;
+        for controller in controllers {
+            let has =
+                |list: &str| list.split_whitespace().any(|c| c == controller);
+            if !has(&available) {
+                let message =
+                    format!("{} controller is not available", controller);
+                return Err(Error::Cgroup(
+                    controllers_file,
+                    not_found(&message),
+                ));
+            }
+            if !has(&enabled) {
+                let enable = format!("+{}", controller);
+                fs::write(&subtree_control, enable).map_err(|error| {
+                    Error::Cgroup(subtree_control.clone(), busy(error))
+                })?;
+            }
+        }
+
+        // An existing cgroup would be shared with another command.
+        fs::create_dir(&path)
+            .map_err(|error| Error::Cgroup(path.clone(), error))?;
+        if let Err(error) = self.limit(&path) {
+            let _ = fs::remove_dir(&path);
+            return Err(error);
+        }
+        Ok(path)
+    }
+
+    fn limit(&self, path: &Path) -> Result<(), Error> {
+        if let Some(max) = self.memory_max {
+            write(&path.join("memory.max"), &max.to_string())?;
+        }
+        if let Some(max) = self.pids_max {
+            write(&path.join("pids.max"), &max.to_string())?;
+        }
+        if let Some((quota, period)) = self.cpu_max {
+            let max = format!("{} {}", quota.as_micros(), period.as_micros());
+            write(&path.join("cpu.max"), &max)?;
+        }
+        for limits in &self.io_max {
+            write(&path.join("io.max"), &limits.line())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the resource usage of every process that was in the cgroup.
+    pub fn stats(&self) -> io::Result<CgroupStats> {
+        let path = self.path()?;
+        let mut stats = CgroupStats::default();
+
+        let cpu_stat = fs::read_to_string(path.join("cpu.stat"))?;
+        for (key, value) in pairs(&cpu_stat) {
+            let value = Duration::from_micros(value);
+            match key {
+                "usage_usec" => stats.cpu_usage = value,
+                "user_usec" => stats.cpu_user = value,
+                "system_usec" => stats.cpu_system = value,
+                _ => {}
+            }
+        }
+
+        stats.memory_peak = fs::read_to_string(path.join("memory.peak"))
+            .ok()
+            .and_then(|peak| peak.trim().parse().ok());
+        if let Ok(events) = fs::read_to_string(path.join("memory.events")) {
+            stats.oom_kills = pairs(&events)
+                .find(|&(key, _)| key == "oom_kill")
+                .map(|(_, value)| value);
+        }
+
+        Ok(stats)
+    }
+
+    /// Kills any processes left in the cgroup and removes it.
+    pub fn remove(&self) -> io::Result<()> {
+        let path = self.path()?;
+
+        // `cgroup.kill` requires Linux 5.14.
+        let _ = fs::write(path.join("cgroup.kill"), "1");
+
+        // Killed processes leave the cgroup asynchronously.
+        let mut tries = 0;
+        loop {
+            match fs::remove_dir(&path) {
+                Err(error) if error.raw_os_error() == Some(libc::EBUSY) => {
+                    tries += 1;
+                    if tries == 100 {
+                        return Err(error);
+                    }
+                    thread::sleep(Duration::from_millis(10));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Returns a name that no other cgroup of the calling process has.
+fn unique_name() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    format!("beach-{}-{}", std::process::id(), count)
+}
+
+/// Returns the cgroup v2 directory of the calling process.
+fn current() -> io::Result<PathBuf> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup")?;
+    let own = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| not_found("process is not in a cgroup v2 hierarchy"))?;
+
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    for line in mountinfo.lines() {
+        let (fields, fstype) = match line.split_once(" - ") {
+            Some((fields, rest)) => (fields, rest.split(' ').next()),
+            None => continue,
+        };
+        if fstype != Some("cgroup2") {
+            continue;
+        }
+        let mut fields = fields.split(' ').skip(3);
+        let (root, mount_point) = match (fields.next(), fields.next()) {
+            (Some(root), Some(mount_point)) => (root, mount_point),
+            _ => continue,
+        };
+        let relative = match Path::new(own).strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => continue,
+        };
+        return Ok(Path::new(mount_point).join(relative));
+    }
+
+    Err(not_found("cgroup v2 is not mounted"))
+}
+
+/// Explains the `EBUSY` returned when enabling controllers in a cgroup that
+/// contains processes.
+fn busy(error: io::Error) -> io::Error {
+    if error.raw_os_error() != Some(libc::EBUSY) {
+        return error;
+    }
+    io::Error::new(
+        error.kind(),
+        "cannot enable controllers in a cgroup that contains processes, \
+         such as the calling process's own; use a delegated parent",
+    )
+}
+
+fn not_found(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, message)
+}
+
+/// Parses the `key value` lines of flat-keyed cgroup files.
+fn pairs(contents: &str) -> impl Iterator<Item = (&str, u64)> {
+    contents.lines().filter_map(|line| {
+        let (key, value) = line.split_once(' ')?;
+        Some((key, value.trim().parse().ok()?))
+    })
+}
+
+fn read(path: &Path) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(|error| Error::Cgroup(path.into(), error))
+}
+
+fn write(path: &Path, contents: &str) -> Result<(), Error> {
+    fs::write(path, contents).map_err(|error| Error::Cgroup(path.into(), error))
+}
@@ -0,0 +1,612 @@
+use crate::{
+    mount,
+    setup::{cvt, write_file},
+    Cgroup, Error, Resource, Stage,
+};
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    ffi::{CStr, OsStr},
+    fmt,
+    fs::{self, File},
+    io, mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+    process::{self, ChildStderr, ChildStdin, ChildStdout, Command},
+    ptr, slice, thread,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    /// The write end of the pipe that setup failures are reported over while
+    /// [`spawn`] runs, which the child inherits along with the memory of the
+    /// thread that forked it.
+    static REPORT_FD: Cell<RawFd> = const { Cell::new(-1) };
+
+    /// The nul-terminated `cgroup.procs` file of the cgroup that the child
+    /// joins and its length, set like [`REPORT_FD`].
+    static CGROUP_PROCS: Cell<(*const u8, usize)> =
+        const { Cell::new((ptr::null(), 0)) };
+}
+
+/// The longest path reported, so that a report fits in `PIPE_BUF` and is
+/// written at once. Longer paths are truncated.
+const MAX_PATH: usize = 4000;
+
+/// What the child is doing, updated before each step of the setup so that a
+/// failure can be attributed. It is sent to the parent as is.
+#[repr(C)]
+pub(crate) struct Progress {
+    stage: i32,
+    errno: i32,
+    /// The index of the resource being limited in [`Resource::ALL`], or -1.
+    resource: i32,
+    /// Whether `path` is a missing mount source rather than the file the
+    /// step operates on.
+    source: i32,
+    /// The length of `path`, or -1 if the step has none.
+    path_len: i32,
+    path: [u8; MAX_PATH],
+}
+
+/// The size of the fields of [`Progress`] before the path.
+const HEADER: usize = 5 * mem::size_of::<i32>();
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            stage: Stage::Exec as i32,
+            errno: 0,
+            resource: -1,
+            source: 0,
+            path_len: -1,
+            path: [0; MAX_PATH],
+        }
+    }
+
+    /// Starts `stage`, forgetting the details of the previous step.
+    pub fn stage(&mut self, stage: Stage) {
+        self.stage = stage as i32;
+        self.resource = -1;
+        self.source = 0;
+        self.path_len = -1;
+    }
+
+    /// Records the nul-terminated `path` that the next step operates on.
+    pub fn path(&mut self, path: &[u8]) {
+        let path = path.strip_suffix(b"\0").unwrap_or(path);
+        let len = path.len().min(MAX_PATH);
+        self.path[..len].copy_from_slice(&path[..len]);
+        self.path_len = len as i32;
+        self.source = 0;
+    }
+
+    /// Records the nul-terminated `path` as a mount source that does not
+    /// exist.
+    pub fn missing_source(&mut self, path: &[u8]) {
+        self.path(path);
+        self.source = 1;
+    }
+
+    /// Records the resource that the next limit applies to.
+    pub fn resource(&mut self, resource: Resource) {
+        self.resource = Resource::ALL
+            .iter()
+            .position(|&other| other == resource)
+            .map_or(-1, |i| i as i32);
+    }
+
+    /// Sends the progress to the parent, if it is listening.
+    fn send(&mut self, result: &io::Result<()>) {
+        self.errno = match result {
+            Ok(()) => {
+                self.stage(Stage::Exec);
+                0
+            }
+            Err(error) => error.raw_os_error().unwrap_or(libc::EIO),
+        };
+
+        let fd = REPORT_FD.with(Cell::get);
+        if fd >= 0 {
+            let len = HEADER + self.path_len.max(0) as usize;
+            unsafe {
+                libc::write(fd, (self as *const Self).cast(), len);
+            }
+        }
+    }
+
+    /// Receives the progress sent before the child executed the program or
+    /// exited, if any.
+    fn receive(fd: RawFd) -> Option<Self> {
+        let mut progress = Self::new();
+        let len = mem::size_of::<Self>();
+        let read =
+            unsafe { libc::read(fd, (&mut progress as *mut Self).cast(), len) };
+        let path_len = usize::try_from(progress.path_len).unwrap_or(0);
+        if read < HEADER as isize || read as usize != HEADER + path_len {
+            return None;
+        }
+        Some(progress)
+    }
+
+    fn path_buf(&self) -> Option<PathBuf> {
+        let len = usize::try_from(self.path_len).ok()?;
+        Some(Path::new(OsStr::from_bytes(&self.path[..len])).to_owned())
+    }
+
+    /// Converts a failure to spawn `command` into the most precise error.
+    fn error(&self, command: &Command, error: io::Error) -> Error {
+        let stage = match Stage::from_raw(self.stage) {
+            Some(stage) => stage,
+            None => return Error::Io(error),
+        };
+        if stage == Stage::Exec {
+            // The program failed to execute after a successful setup.
+            return match error.raw_os_error() {
+                Some(libc::ENOENT) | Some(libc::EACCES) => {
+                    Error::ProgramNotFound(command.get_program().to_owned())
+                }
+                _ => Error::Setup(stage, None, error),
+            };
+        }
+
+        let error = io::Error::from_raw_os_error(self.errno);
+        let resource = usize::try_from(self.resource)
+            .ok()
+            .and_then(|i| Resource::ALL.get(i).copied());
+        match (stage, self.errno, self.path_buf(), resource) {
+            (Stage::Cgroup, _, Some(path), _) => Error::Cgroup(path, error),
+            (Stage::Rlimit, _, _, Some(resource)) => {
+                Error::Rlimit(resource, error)
+            }
+            (Stage::Root, libc::ENOENT, Some(path), _) => {
+                Error::RootNotFound(path)
+            }
+            (Stage::Root, libc::ENOTDIR, Some(path), _) => {
+                Error::RootNotDirectory(path)
+            }
+            (Stage::Mount, libc::ENOENT, Some(path), _) if self.source != 0 => {
+                Error::MountSourceNotFound(path)
+            }
+            (stage, _, path, _) => Error::Setup(stage, path, error),
+        }
+    }
+}
+
+/// Runs `setup` in the child, reporting the step it fails at, or that the
+/// program is about to be executed, to the parent.
+///
+/// Only async-signal-safe operations are used.
+pub(crate) fn report<F>(setup: F) -> io::Result<()>
+where
+    F: FnOnce(&mut Progress) -> io::Result<()>,
+{
+    let mut progress = Progress::new();
+    let result = setup(&mut progress);
+    progress.send(&result);
+    result
+}
+
+/// Moves the child into the cgroup created by [`spawn`].
+///
+/// Only async-signal-safe operations are used.
+pub(crate) fn join_cgroup(progress: &mut Progress) -> io::Result<()> {
+    progress.stage(Stage::Cgroup);
+    let (procs, len) = CGROUP_PROCS.with(Cell::get);
+    if procs.is_null() {
+        // The command was not spawned by `Chroot::spawn`.
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    // SAFETY: `spawn` keeps the path alive until the child is forked.
+    let procs = unsafe { slice::from_raw_parts(procs, len) };
+    progress.path(procs);
+    write_file(procs, b"0")
+}
+
+/// Spawns `command` in `cgroup`, which is removed if this fails, telling
+/// setup failures apart from failures to execute the program.
+///
+/// `min_fd` is the lowest descriptor that pipes to the child may use, so that
+/// passed descriptors do not replace them.
+pub(crate) fn spawn(
+    command: &mut Command,
+    min_fd: RawFd,
+    cgroup: Option<Cgroup>,
+    seccomp: bool,
+) -> Result<Child, Error> {
+    let started = Instant::now();
+    let procs = cgroup
+        .as_ref()
+        .map(|cgroup| mount::cstring(&cgroup.path()?.join("cgroup.procs")))
+        .transpose();
+    let result = procs
+        .map_err(Error::from)
+        .and_then(|procs| start(command, min_fd, procs.as_deref()));
+
+    match result {
+        Ok(mut child) => Ok(Child {
+            stdin: child.stdin.take(),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            pid: child.id() as libc::pid_t,
+            started,
+            cgroup,
+            seccomp,
+            timed_out: false,
+            outcome: None,
+        }),
+        Err(error) => {
+            if let Some(cgroup) = &cgroup {
+                let _ = cgroup.remove();
+            }
+            Err(error)
+        }
+    }
+}
+
+fn start(
+    command: &mut Command,
+    min_fd: RawFd,
+    procs: Option<&CStr>,
+) -> Result<process::Child, Error> {
+    // `Command` reports `exec` failures over a pipe of its own, which it
+    // opens at the lowest free descriptors.
+    let reserved = reserve_below(min_fd)?;
+
+    let (read_fd, write_fd) = unsafe {
+        let mut fds = [0; 2];
+        cvt(libc::pipe2(
+            fds.as_mut_ptr(),
+            libc::O_CLOEXEC | libc::O_NONBLOCK,
+        ))?;
+        let write_fd = libc::fcntl(fds[1], libc::F_DUPFD_CLOEXEC, min_fd);
+        let error = io::Error::last_os_error();
+        libc::close(fds[1]);
+        if write_fd == -1 {
+            libc::close(fds[0]);
+            return Err(error.into());
+        }
+        (fds[0], write_fd)
+    };
+
+    let procs = procs.map_or((ptr::null(), 0), |procs| {
+        let procs = procs.to_bytes_with_nul();
+        (procs.as_ptr(), procs.len())
+    });
+    REPORT_FD.with(|fd| fd.set(write_fd));
+    CGROUP_PROCS.with(|path| path.set(procs));
+    let result = command.spawn();
+    REPORT_FD.with(|fd| fd.set(-1));
+    CGROUP_PROCS.with(|path| path.set((ptr::null(), 0)));
+    drop(reserved);
+
+    // Every process that could write has exited or executed by now.
+    let progress = unsafe {
+        libc::close(write_fd);
+        let progress = Progress::receive(read_fd);
+        libc::close(read_fd);
+        progress
+    };
+
+    // Without a report, the child could not even be forked.
+    result.map_err(|error| match progress {
+        Some(progress) => progress.error(command, error),
+        None => Error::Io(error),
+    })
+}
+
+/// Opens `/dev/null` at every free descriptor below `min_fd` until the
+/// returned files are dropped.
+fn reserve_below(min_fd: RawFd) -> io::Result<Vec<File>> {
+    let mut reserved = Vec::new();
+    loop {
+        let file = File::open("/dev/null")?;
+        if file.as_raw_fd() >= min_fd {
+            return Ok(reserved);
+        }
+        reserved.push(file);
+    }
+}
+
+/// A sandboxed process, as returned by
+/// [`Chroot::spawn`](crate::Chroot::spawn).
+///
+/// Unlike [`std::process::Child`], waiting reports why the process ended and
+/// the resources it used.
+///
+/// # Examples
+///
+/// ```
+/// # return;
+/// use beach::{Chroot, Exit};
+/// use std::time::Duration;
+///
+/// let chroot = Chroot::new();
+/// let mut command = chroot.try_command("/path/to/root", "make").unwrap();
+/// let mut child = chroot.spawn(&mut command).unwrap();
+///
+/// let outcome = child.wait_timeout(Duration::from_secs(600)).unwrap();
+/// match outcome.exit {
+///     Exit::Code(0) => {}
+///     Exit::Timeout => eprintln!("make took too long"),
+///     exit => eprintln!("make failed: {}", exit),
+/// }
+/// println!("{:?} of user time", outcome.usage.user_time);
+/// ```
+#[derive(Debug)]
+pub struct Child {
+    /// The handle for writing to the child's standard input, if piped.
+    pub stdin: Option<ChildStdin>,
+    /// The handle for reading from the child's standard output, if piped.
+    pub stdout: Option<ChildStdout>,
+    /// The handle for reading from the child's standard error, if piped.
+    pub stderr: Option<ChildStderr>,
+    pid: libc::pid_t,
+    started: Instant,
+    cgroup: Option<Cgroup>,
+    seccomp: bool,
+    timed_out: bool,
+    outcome: Option<Outcome>,
+}
+
+/// How a [`Child`] ended and the resources it used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Outcome {
+    /// Why the process ended.
+    pub exit: Exit,
+    /// The resources used by the process and the descendants it waited for.
+    pub usage: Usage,
+}
+
+/// Why a [`Child`] ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Exit {
+    /// The process exited with a status code.
+    Code(i32),
+    /// The process was killed by a signal.
+    Signal(i32),
+    /// The process was killed by its seccomp filter, with `SIGSYS`.
+    Seccomp,
+    /// The process was killed by the OOM killer of its cgroup.
+    OutOfMemory,
+    /// The process was killed by [`Child::wait_timeout`].
+    Timeout,
+}
+
+/// Resources used by a [`Child`], as reported by `getrusage(2)`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Usage {
+    /// The time from spawning to reaping the process.
+    pub elapsed: Duration,
+    /// The CPU time spent in user mode.
+    pub user_time: Duration,
+    /// The CPU time spent in kernel mode.
+    pub system_time: Duration,
+    /// The largest resident set size in bytes.
+    pub max_rss: u64,
+    /// The page faults serviced without I/O.
+    pub minor_faults: u64,
+    /// The page faults that required I/O.
+    pub major_faults: u64,
+    /// The blocks read from filesystems.
+    pub block_reads: u64,
+    /// The blocks written to filesystems.
+    pub block_writes: u64,
+    /// The times the process gave up the CPU while waiting.
+    pub voluntary_switches: u64,
+    /// The times the process was preempted.
+    pub involuntary_switches: u64,
+}
+
+impl Outcome {
+    /// Returns whether the process exited with status 0.
+    #[inline]
+    pub fn success(&self) -> bool {
+        self.exit == Exit::Code(0)
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Code(code) => write!(f, "exit status: {}", code),
+            Self::Signal(signal) => write!(f, "killed by signal {}", signal),
+            Self::Seccomp => f.write_str("killed by seccomp"),
+            Self::OutOfMemory => f.write_str("killed by the OOM killer"),
+            Self::Timeout => f.write_str("timed out"),
+        }
+    }
+}
+
+impl Child {
+    /// Returns the process ID of the child.
+    #[inline]
+    pub fn id(&self) -> u32 {
+        self.pid as u32
+    }
+
+    /// Returns the cgroup created for the child, if
+    /// [`Chroot::cgroup`](crate::Chroot::cgroup) was used.
+    ///
+    /// It is removed along with any processes left in it when the `Child` is
+    /// dropped after being waited for, so read its
+    /// [`stats`](Cgroup::stats) before then.
+    #[inline]
+    pub fn cgroup(&self) -> Option<&Cgroup> {
+        self.cgroup.as_ref()
+    }
+
+    /// Kills the child with `SIGKILL`, along with every other process in its
+    /// cgroup, if any.
+    pub fn kill(&mut self) -> io::Result<()> {
+        if self.outcome.is_some() {
+            return Ok(());
+        }
+        if let Some(cgroup) = &self.cgroup {
+            // `cgroup.kill` requires Linux 5.14.
+            let _ = fs::write(cgroup.path()?.join("cgroup.kill"), "1");
+        }
+        unsafe {
+            cvt(libc::kill(self.pid, libc::SIGKILL))?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the child to end.
+    pub fn wait(&mut self) -> io::Result<Outcome> {
+        self.wait4(0)
+            .map(|outcome| outcome.expect("child was waited for"))
+    }
+
+    /// Returns how the child ended if it has, without blocking.
+    pub fn try_wait(&mut self) -> io::Result<Option<Outcome>> {
+        self.wait4(libc::WNOHANG)
+    }
+
+    /// Waits for the child to end, killing it if it runs for longer than
+    /// `timeout`, in which case it ends with [`Exit::Timeout`].
+    pub fn wait_timeout(&mut self, timeout: Duration) -> io::Result<Outcome> {
+        if let Some(outcome) = self.try_wait()? {
+            return Ok(outcome);
+        }
+
+        let deadline = Instant::now() + timeout;
+        match pidfd_open(self.pid) {
+            Ok(pidfd) => {
+                let result = poll_until(pidfd, deadline);
+                unsafe {
+                    libc::close(pidfd);
+                }
+                result?;
+            }
+            // `pidfd_open(2)` requires Linux 5.3.
+            Err(_) => {
+                let mut delay = Duration::from_millis(1);
+                while self.try_wait()?.is_none() {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    thread::sleep(delay.min(deadline - now));
+                    delay = (delay * 2).min(Duration::from_millis(50));
+                }
+            }
+        }
+
+        if let Some(outcome) = self.try_wait()? {
+            return Ok(outcome);
+        }
+        self.kill()?;
+        self.timed_out = true;
+        self.wait()
+    }
+
+    fn wait4(&mut self, options: libc::c_int) -> io::Result<Option<Outcome>> {
+        if let Some(outcome) = self.outcome {
+            return Ok(Some(outcome));
+        }
+
+        let mut status = 0;
+        let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+        let pid = loop {
+            let pid = unsafe {
+                libc::wait4(self.pid, &mut status, options, &mut rusage)
+            };
+            match cvt(pid) {
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                result => break result?,
+            }
+        };
+        if pid == 0 {
+            return Ok(None);
+        }
+
+        let outcome = Outcome {
+            exit: self.exit(status),
+            usage: Usage::new(&rusage, self.started.elapsed()),
+        };
+        self.outcome = Some(outcome);
+        Ok(Some(outcome))
+    }
+
+    fn exit(&self, status: libc::c_int) -> Exit {
+        if libc::WIFEXITED(status) {
+            return Exit::Code(libc::WEXITSTATUS(status));
+        }
+
+        let signal = libc::WTERMSIG(status);
+        let oom_kills = || {
+            let stats = self.cgroup.as_ref()?.stats().ok()?;
+            stats.oom_kills
+        };
+        match signal {
+            libc::SIGKILL if self.timed_out => Exit::Timeout,
+            libc::SIGKILL if oom_kills().is_some_and(|kills| kills > 0) => {
+                Exit::OutOfMemory
+            }
+            libc::SIGSYS if self.seccomp => Exit::Seccomp,
+            signal => Exit::Signal(signal),
+        }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        // A cgroup still in use by a running child is left alone.
+        if let (Some(_), Some(cgroup)) = (&self.outcome, &self.cgroup) {
+            let _ = cgroup.remove();
+        }
+    }
+}
+
+impl Usage {
+    fn new(rusage: &libc::rusage, elapsed: Duration) -> Self {
+        let time = |time: libc::timeval| {
+            Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+        };
+        Self {
+            elapsed,
+            user_time: time(rusage.ru_utime),
+            system_time: time(rusage.ru_stime),
+            // Linux reports kibibytes.
+            max_rss: rusage.ru_maxrss as u64 * 1024,
+            minor_faults: rusage.ru_minflt as u64,
+            major_faults: rusage.ru_majflt as u64,
+            block_reads: rusage.ru_inblock as u64,
+            block_writes: rusage.ru_oublock as u64,
+            voluntary_switches: rusage.ru_nvcsw as u64,
+            involuntary_switches: rusage.ru_nivcsw as u64,
+        }
+    }
+}
+
+fn pidfd_open(pid: libc::pid_t) -> io::Result<RawFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    cvt(fd as libc::c_int)
+}
+
+/// Waits until `pidfd` becomes readable, which happens when the process
+/// exits, or until `deadline`.
+fn poll_until(pidfd: RawFd, deadline: Instant) -> io::Result<()> {
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        // Rounded up so that the deadline is not missed by a fraction.
+        let millis = remaining.as_micros().div_ceil(1000);
+        let timeout = millis.min(libc::c_int::MAX as u128) as libc::c_int;
+        let mut pollfd = libc::pollfd {
+            fd: pidfd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match cvt(unsafe { libc::poll(&mut pollfd, 1, timeout) }) {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            result => return result.map(drop),
+        }
+    }
+}
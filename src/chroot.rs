@@ -1,12 +1,84 @@
+use crate::{
+    caps::{self, CapSet, Capability, Caps},
+    cgroup::Cgroup,
+    child::{self, Child},
+    fd::{Fds, PassFd},
+    ids::{IdResolver, Ids},
+    landlock::Ruleset,
+    mount::{self, Mount, MountPoints},
+    net::Network,
+    overlay::Overlay,
+    path,
+    rlimit::{Resource, Rlimit},
+    seccomp::{Arch, Filter},
+    setup::{IdMaps, Setup, Stage},
+    Error,
+};
 use std::{
-    ffi::{OsStr, OsString},
-    path::Path,
+    env,
+    ffi::{CString, OsStr, OsString},
+    io, mem,
+    os::unix::{ffi::OsStrExt, io::RawFd, process::CommandExt},
+    path::{Path, PathBuf},
     process::Command,
+    time::Duration,
 };
 
-/// A wrapper for [`chroot(1)`](https://www.gnu.org/software/coreutils/chroot).
+/// The search path used by `execvp` when `PATH` is unset.
+const DEFAULT_PATH: &str = "/bin:/usr/bin";
+
+/// The `PATH` given to commands that do not inherit or set one.
+const GUEST_PATH: &str =
+    "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// The mechanism used by [`Chroot`] to enter the root directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Backend {
+    /// Calls [`chroot(2)`](http://man7.org/linux/man-pages/man2/chroot.2.html)
+    /// and switches credentials in the child process right before `program`
+    /// is executed.
+    ///
+    /// User and group names are resolved in the parent process using the
+    /// `/etc/passwd` and `/etc/group` files within the root directory. See
+    /// [`IdResolver`] for details.
+    Native,
+    /// Like [`Native`](Self::Native), but enters the root directory with
+    /// [`pivot_root(2)`](http://man7.org/linux/man-pages/man2/pivot_root.2.html)
+    /// within a new mount namespace.
+    ///
+    /// Unlike `chroot(2)`, the host's root is detached afterwards, so it
+    /// cannot be escaped by a process that keeps root privileges. This
+    /// requires `CAP_SYS_ADMIN` unless [`Chroot::unprivileged`] is used.
+    PivotRoot,
+    /// Runs `program` through
+    /// [`chroot(1)`](https://www.gnu.org/software/coreutils/chroot), which
+    /// must be in `PATH`.
+    ///
+    /// User and group names are resolved by `chroot(1)` itself.
+    Coreutils,
+}
+
+impl Default for Backend {
+    #[inline]
+    fn default() -> Self {
+        Backend::Native
+    }
+}
+
+/// Runs a program with a different root directory.
+///
+/// **Note:** Running `chroot` requires root privileges, unless
+/// [`unprivileged`](Self::unprivileged) is used.
 ///
-/// **Note:** Running `chroot` requires root privileges.
+/// Commands start with an empty environment, apart from a default `PATH`
+/// suitable for the root directory. Variables can be passed through from the
+/// calling process with [`pass_env`](Self::pass_env) or set with
+/// [`env`](Self::env).
 ///
 /// # Examples
 ///
@@ -20,9 +92,31 @@ use std::{
 /// ```
 #[derive(Clone, Debug)]
 pub struct Chroot {
+    backend: Backend,
     skip_chdir: bool,
-    user_spec: Option<OsString>,
-    groups: Option<OsString>,
+    current_dir: Option<PathBuf>,
+    user: Option<OsString>,
+    group: Option<OsString>,
+    groups: Option<Vec<OsString>>,
+    unprivileged: bool,
+    mounts: Vec<Mount>,
+    overlay: Option<Overlay>,
+    seccomp: Option<Filter>,
+    caps: Caps,
+    no_new_privs: bool,
+    rlimits: Vec<Rlimit>,
+    cgroup: Option<Cgroup>,
+    network: Network,
+    pid_namespace: bool,
+    hostname: Option<OsString>,
+    domainname: Option<OsString>,
+    ipc_namespace: bool,
+    time_offsets: Option<(Duration, Duration)>,
+    landlock: Option<Ruleset>,
+    inherit_env: bool,
+    pass_env: Vec<OsString>,
+    envs: Vec<(OsString, OsString)>,
+    pass_fds: Vec<PassFd>,
 }
 
 impl Default for Chroot {
@@ -38,27 +132,628 @@ impl Chroot {
     #[inline]
     pub const fn new() -> Self {
         Self {
+            backend: Backend::Native,
             skip_chdir: false,
-            user_spec: None,
+            current_dir: None,
+            user: None,
+            group: None,
             groups: None,
+            unprivileged: false,
+            mounts: Vec::new(),
+            overlay: None,
+            seccomp: None,
+            caps: Caps::new(),
+            no_new_privs: false,
+            rlimits: Vec::new(),
+            cgroup: None,
+            network: Network::Host,
+            pid_namespace: false,
+            hostname: None,
+            domainname: None,
+            ipc_namespace: false,
+            time_offsets: None,
+            landlock: None,
+            inherit_env: false,
+            pass_env: Vec::new(),
+            envs: Vec::new(),
+            pass_fds: Vec::new(),
+        }
+    }
+
+    /// Use `backend` to enter the root directory.
+    ///
+    /// The default is [`Backend::Native`].
+    #[inline]
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Enter the root directory from within a new user namespace, which does
+    /// not require any privileges.
+    ///
+    /// The calling user and group are mapped to root within the namespace,
+    /// or to the user and group given to [`user`](Self::user) or
+    /// [`user_group`](Self::user_group). Only a single user and group can be
+    /// mapped without privileges, so supplementary groups are unsupported.
+    ///
+    /// This requires [`Backend::Native`] or [`Backend::PivotRoot`] and a
+    /// kernel that permits unprivileged user namespaces.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// beach::Chroot::new()
+    ///     .unprivileged()
+    ///     .user_group("1000", "1000")
+    ///     .command("/path/to/root", "id")
+    ///     .spawn();
+    /// ```
+    #[inline]
+    pub fn unprivileged(mut self) -> Self {
+        self.unprivileged = true;
+        self
+    }
+
+    /// Mounts `overlay` over the root directory before anything else, so that
+    /// the command's changes do not affect its lower directories.
+    ///
+    /// User and group names are resolved, and the program is found, within
+    /// the overlay's directories rather than the root directory itself.
+    #[inline]
+    pub fn overlay(mut self, overlay: Overlay) -> Self {
+        self.overlay = Some(overlay);
+        self
+    }
+
+    /// Bind-mounts `source` on the host to `target` within the root directory.
+    ///
+    /// Mounts are made in a private mount namespace, so they are only visible
+    /// to the command and disappear when it exits. They are performed in the
+    /// order they are added.
+    ///
+    /// The mount point must exist within the root directory, since anything
+    /// created there would outlive the command. Only within a filesystem
+    /// mounted for the command, such as a [`tmpfs`](Self::tmpfs), the
+    /// [`minimal_dev`](Self::minimal_dev) or an [`overlay`](Self::overlay)
+    /// without an upper directory, are missing mount points created.
+    /// Otherwise, [`Error::MountPointNotFound`] is reported.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// beach::Chroot::new()
+    ///     .bind("/home/me/src", "/src")
+    ///     .bind_ro("/var/cache/ocean", "/cache")
+    ///     .command("/path/to/root", "make")
+    ///     .args(&["-C", "/src"])
+    ///     .spawn();
+    /// ```
+    pub fn bind<S, T>(self, source: S, target: T) -> Self
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        self.bind_impl(source.as_ref(), target.as_ref(), false)
+    }
+
+    /// Bind-mounts `source` on the host to `target` within the root directory
+    /// as read-only.
+    ///
+    /// Mounts beneath `source` are made read-only as well. See
+    /// [`bind`](Self::bind) for details.
+    pub fn bind_ro<S, T>(self, source: S, target: T) -> Self
+    where
+        S: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        self.bind_impl(source.as_ref(), target.as_ref(), true)
+    }
+
+    fn bind_impl(mut self, source: &Path, target: &Path, ro: bool) -> Self {
+        self.mounts.push(Mount::Bind {
+            source: source.to_owned(),
+            target: target.to_owned(),
+            read_only: ro,
+        });
+        self
+    }
+
+    /// Mounts a `proc` filesystem at `/proc` within the root directory.
+    ///
+    /// If a new `proc` filesystem cannot be mounted, such as in an
+    /// [`unprivileged`](Self::unprivileged) user namespace, the host's
+    /// `/proc` is bind-mounted instead.
+    #[inline]
+    pub fn mount_proc(mut self) -> Self {
+        self.mounts.push(Mount::Proc);
+        self
+    }
+
+    /// Bind-mounts the host's `/sys` at `/sys` within the root directory as
+    /// read-only.
+    #[inline]
+    pub fn mount_sys(self) -> Self {
+        self.bind_impl(Path::new("/sys"), Path::new("/sys"), true)
+    }
+
+    /// Mounts a `tmpfs` at `/dev` within the root directory, containing only
+    /// `null`, `zero`, `full`, `random`, `urandom` and `tty` from the host,
+    /// along with the standard `fd`, `stdin`, `stdout` and `stderr` links.
+    ///
+    /// The links refer to `/proc`, so this is best used with
+    /// [`mount_proc`](Self::mount_proc).
+    #[inline]
+    pub fn minimal_dev(mut self) -> Self {
+        self.mounts.push(Mount::Dev);
+        self
+    }
+
+    /// Mounts a world-writable `tmpfs` at `target` within the root directory.
+    ///
+    /// The filesystem is limited to `size` bytes, or to half of the memory if
+    /// `size` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// beach::Chroot::new()
+    ///     .mount_proc()
+    ///     .minimal_dev()
+    ///     .tmpfs("/tmp", 64 << 20)
+    ///     .command("/path/to/root", "ls")
+    ///     .spawn();
+    /// ```
+    pub fn tmpfs<T: AsRef<Path>>(mut self, target: T, size: u64) -> Self {
+        self.mounts.push(Mount::Tmpfs {
+            target: target.as_ref().to_owned(),
+            size,
+        });
+        self
+    }
+
+    /// Restricts the system calls the command can make with `filter`.
+    ///
+    /// The filter is installed right before the program is executed, after
+    /// the root directory is entered and credentials are changed. If the
+    /// process no longer has `CAP_SYS_ADMIN` at that point,
+    /// `PR_SET_NO_NEW_PRIVS` is set, as the kernel requires.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// use beach::{seccomp::Filter, Chroot};
+    ///
+    /// Chroot::new()
+    ///     .seccomp(Filter::default_profile())
+    ///     .command("/path/to/root", "make")
+    ///     .spawn();
+    /// ```
+    #[inline]
+    pub fn seccomp(mut self, filter: Filter) -> Self {
+        self.seccomp = Some(filter);
+        self
+    }
+
+    /// Limits the command to the capabilities in `set`.
+    ///
+    /// This sets every capability set, including the bounding and ambient
+    /// sets, so the command keeps exactly these capabilities whether it runs
+    /// as root or as another [`user`](Self::user).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// use beach::{Capability, Chroot};
+    ///
+    /// Chroot::new()
+    ///     .capabilities(Capability::Chown.into())
+    ///     .command("/path/to/root", "chown")
+    ///     .args(&["nobody", "/file"])
+    ///     .spawn();
+    /// ```
+    #[inline]
+    pub fn capabilities(self, set: CapSet) -> Self {
+        self.bounding_caps(set)
+            .permitted_caps(set)
+            .effective_caps(set)
+            .inheritable_caps(set)
+            .ambient_caps(set)
+    }
+
+    /// Drops every capability not in `set` from the bounding set, which
+    /// limits the capabilities the command and its children can ever gain.
+    ///
+    /// Programs executed as root gain every capability in the bounding set.
+    /// Dropping capabilities requires `CAP_SETPCAP`.
+    #[inline]
+    pub fn bounding_caps(mut self, set: CapSet) -> Self {
+        self.caps.bounding = Some(set);
+        self
+    }
+
+    /// Sets the permitted capability set after credentials are changed.
+    ///
+    /// Capabilities can only be removed from the permitted set. It is kept
+    /// when switching away from root so that
+    /// [`ambient_caps`](Self::ambient_caps) can be raised.
+    #[inline]
+    pub fn permitted_caps(mut self, set: CapSet) -> Self {
+        self.caps.permitted = Some(set);
+        self
+    }
+
+    /// Sets the effective capability set after credentials are changed.
+    ///
+    /// This must be a subset of the permitted set. Note that executing a
+    /// program recomputes the effective set, so this mostly matters to the
+    /// setup itself, such as whether a [`seccomp`](Self::seccomp) filter
+    /// requires `PR_SET_NO_NEW_PRIVS`.
+    #[inline]
+    pub fn effective_caps(mut self, set: CapSet) -> Self {
+        self.caps.effective = Some(set);
+        self
+    }
+
+    /// Sets the inheritable capability set after credentials are changed.
+    #[inline]
+    pub fn inheritable_caps(mut self, set: CapSet) -> Self {
+        self.caps.inheritable = Some(set);
+        self
+    }
+
+    /// Sets the ambient capability set, which a program executed by a user
+    /// other than root keeps.
+    ///
+    /// These capabilities are added to the inheritable set, and must be in
+    /// the permitted set.
+    #[inline]
+    pub fn ambient_caps(mut self, set: CapSet) -> Self {
+        self.caps.ambient = Some(set);
+        self
+    }
+
+    /// Sets `PR_SET_NO_NEW_PRIVS`, so that the command and its children
+    /// cannot gain privileges through set-user-ID programs or file
+    /// capabilities.
+    #[inline]
+    pub fn no_new_privs(mut self) -> Self {
+        self.no_new_privs = true;
+        self
+    }
+
+    /// Limits the command's use of `resource` to `soft`, which it may raise up
+    /// to `hard`. A limit of `u64::MAX` is unlimited.
+    ///
+    /// Limits are applied in the child before credentials are changed and
+    /// replace any earlier limit on the same resource. Raising a hard limit
+    /// requires `CAP_SYS_RESOURCE`, which a new user namespace does not
+    /// grant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// use beach::{Chroot, Resource};
+    ///
+    /// Chroot::new()
+    ///     .rlimit(Resource::Cpu, 60, 60)
+    ///     .rlimit(Resource::NProc, 256, 256)
+    ///     .rlimit(Resource::FileSize, 1 << 30, 1 << 30)
+    ///     .rlimit(Resource::Core, 0, 0)
+    ///     .command("/path/to/root", "make")
+    ///     .spawn();
+    /// ```
+    pub fn rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Self {
+        self.rlimits.retain(|limit| limit.resource != resource);
+        self.rlimits.push(Rlimit {
+            resource,
+            soft,
+            hard,
+        });
+        self
+    }
+
+    /// Sets the network access of the command.
+    ///
+    /// The default is [`Network::Host`]. Otherwise, the command runs in a new
+    /// network namespace, which requires `CAP_SYS_ADMIN` unless
+    /// [`unprivileged`](Self::unprivileged) is used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// use beach::{Chroot, Network};
+    ///
+    /// // Tests can bind to 127.0.0.1, but cannot reach the internet.
+    /// Chroot::new()
+    ///     .network(Network::Loopback)
+    ///     .command("/path/to/root", "make")
+    ///     .arg("check")
+    ///     .spawn();
+    /// ```
+    #[inline]
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Runs the command in a new PID namespace, under a minimal init.
+    ///
+    /// The init is PID 1 within the namespace and reaps orphaned processes.
+    /// Signals sent to the spawned `Child` are forwarded to the command, and
+    /// the `Child` exits with the command's status. Once the command exits,
+    /// every process left in the namespace is killed, including daemons it
+    /// started.
+    ///
+    /// The init is subject to the [`seccomp`](Self::seccomp) filter, which
+    /// must allow it to call `wait4`, `kill`, `write` and `exit_group`. This
+    /// requires `CAP_SYS_ADMIN` unless [`unprivileged`](Self::unprivileged)
+    /// is used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// beach::Chroot::new()
+    ///     .pid_namespace()
+    ///     .mount_proc()
+    ///     .command("/path/to/root", "make")
+    ///     .spawn();
+    /// ```
+    #[inline]
+    pub fn pid_namespace(mut self) -> Self {
+        self.pid_namespace = true;
+        self
+    }
+
+    /// Sets the hostname seen by the command, within a new UTS namespace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// beach::Chroot::new()
+    ///     .hostname("builder")
+    ///     .domainname("ocean.invalid")
+    ///     .ipc_namespace()
+    ///     .command("/path/to/root", "make")
+    ///     .spawn();
+    /// ```
+    pub fn hostname<H: AsRef<OsStr>>(mut self, hostname: H) -> Self {
+        self.hostname = Some(hostname.as_ref().to_owned());
+        self
+    }
+
+    /// Sets the NIS domain name seen by the command, within a new UTS
+    /// namespace.
+    pub fn domainname<D: AsRef<OsStr>>(mut self, domainname: D) -> Self {
+        self.domainname = Some(domainname.as_ref().to_owned());
+        self
+    }
+
+    /// Runs the command in a new IPC namespace, with its own System V IPC
+    /// objects and POSIX message queues.
+    #[inline]
+    pub fn ipc_namespace(mut self) -> Self {
+        self.ipc_namespace = true;
+        self
+    }
+
+    /// Runs the command in a new time namespace, where the monotonic and
+    /// boot-time clocks are ahead of the host's by the given offsets.
+    ///
+    /// This requires Linux 5.6.
+    #[inline]
+    pub fn time_namespace(
+        mut self,
+        monotonic: Duration,
+        boottime: Duration,
+    ) -> Self {
+        self.time_offsets = Some((monotonic, boottime));
+        self
+    }
+
+    /// Runs the command and its descendants in a new cgroup with the limits
+    /// of `cgroup`, which is created when the command is spawned.
+    ///
+    /// The command must be spawned with [`spawn`](Self::spawn), which
+    /// returns a [`Child`] whose [`cgroup`](Child::cgroup) is the new one.
+    /// [`Command::spawn`] fails with `EINVAL` instead.
+    #[inline]
+    pub fn cgroup(mut self, cgroup: Cgroup) -> Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
+    /// Restricts the command's filesystem access with a Landlock `ruleset`.
+    ///
+    /// The ruleset is applied after the root directory is entered and
+    /// credentials are changed, so its paths are resolved within the root.
+    /// Use [`Ruleset::enforcement`] to find out which access rights the
+    /// kernel restricts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// use beach::{landlock::Ruleset, Chroot};
+    ///
+    /// let ruleset = Ruleset::new()
+    ///     .execute("/usr")
+    ///     .read_only("/etc")
+    ///     .read_write("/build");
+    ///
+    /// Chroot::new()
+    ///     .bind("/home/me/src", "/build")
+    ///     .landlock(ruleset)
+    ///     .command("/path/to/root", "make")
+    ///     .args(&["-C", "/build"])
+    ///     .spawn();
+    /// ```
+    #[inline]
+    pub fn landlock(mut self, ruleset: Ruleset) -> Self {
+        self.landlock = Some(ruleset);
+        self
+    }
+
+    /// Sets the environment variable `key` to `value` for the command.
+    ///
+    /// This takes precedence over variables passed through from the calling
+    /// process.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// beach::Chroot::new()
+    ///     .env("HOME", "/build")
+    ///     .env("LANG", "C.UTF-8")
+    ///     .pass_env("TERM")
+    ///     .command("/path/to/root", "make")
+    ///     .spawn();
+    /// ```
+    pub fn env<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let key = key.as_ref().to_owned();
+        self.envs.retain(|(k, _)| *k != key);
+        self.envs.push((key, value.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets multiple environment variables for the command.
+    ///
+    /// See [`env`](Self::env) for details.
+    pub fn envs<I, K, V>(self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        vars.into_iter()
+            .fold(self, |chroot, (key, value)| chroot.env(key, value))
+    }
+
+    /// Passes the environment variable `key` of the calling process through
+    /// to the command, if it is set.
+    pub fn pass_env<K: AsRef<OsStr>>(mut self, key: K) -> Self {
+        self.pass_env.push(key.as_ref().to_owned());
+        self
+    }
+
+    /// Passes the whole environment of the calling process through to the
+    /// command, instead of starting with an empty one.
+    ///
+    /// This leaks variables such as credentials and host paths to the
+    /// command, so prefer [`pass_env`](Self::pass_env).
+    #[inline]
+    pub fn inherit_env(mut self) -> Self {
+        self.inherit_env = true;
+        self
+    }
+
+    /// Passes the descriptor `host_fd` of the calling process to the command
+    /// as `child_fd`, for example a jobserver pipe or a listening socket.
+    ///
+    /// Apart from stdin, stdout and stderr, the command receives no other
+    /// descriptors, whether or not they are close-on-exec. `host_fd` stays
+    /// open in the calling process, and must remain so until the command is
+    /// spawned.
+    ///
+    /// Spawn the command with [`spawn`](Self::spawn), which keeps the pipe
+    /// that `Command` reports `exec` failures over from being opened at
+    /// `child_fd`. [`Command::spawn`] may otherwise mistake a failure for
+    /// success when `child_fd` is not open in the calling process.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// use std::os::unix::io::AsRawFd;
+    ///
+    /// let socket = std::net::TcpListener::bind("127.0.0.1:8080").unwrap();
+    /// beach::Chroot::new()
+    ///     .pass_fd(socket.as_raw_fd(), 3)
+    ///     .env("LISTEN_FDS", "1")
+    ///     .command("/path/to/root", "/usr/bin/server")
+    ///     .spawn();
+    /// ```
+    pub fn pass_fd(mut self, host_fd: RawFd, child_fd: RawFd) -> Self {
+        self.pass_fds.retain(|pass| pass.child != child_fd);
+        self.pass_fds.push(PassFd {
+            host: host_fd,
+            child: child_fd,
+        });
+        self
+    }
+
+    /// Returns the value of `key` in the command's environment, apart from
+    /// changes made to the returned `Command`.
+    fn env_var(&self, key: &OsStr) -> Option<OsString> {
+        if let Some((_, value)) = self.envs.iter().find(|(k, _)| k == key) {
+            return Some(value.clone());
+        }
+        if self.inherit_env || self.pass_env.iter().any(|k| k == key) {
+            return env::var_os(key);
+        }
+        None
+    }
+
+    /// Applies the environment policy to `command`.
+    fn apply_env(&self, command: &mut Command) {
+        if !self.inherit_env {
+            command.env_clear();
+            for key in &self.pass_env {
+                if let Some(value) = env::var_os(key) {
+                    command.env(key, value);
+                }
+            }
+            if self.env_var(OsStr::new("PATH")).is_none() {
+                command.env("PATH", GUEST_PATH);
+            }
+        }
+        for (key, value) in &self.envs {
+            command.env(key, value);
         }
     }
 
     /// Do not change the working directory to `/`.
+    ///
+    /// This is unsupported by [`Backend::PivotRoot`], since the previous
+    /// working directory is detached along with the host's root.
     #[inline]
     pub fn skip_chdir(mut self) -> Self {
         self.skip_chdir = true;
         self
     }
 
-    /// Specify the user to use.
+    /// Changes the working directory to `dir` within the root directory,
+    /// instead of `/`.
+    ///
+    /// This is unsupported by [`Backend::Coreutils`], and has no effect with
+    /// [`skip_chdir`](Self::skip_chdir).
+    #[inline]
+    pub fn current_dir<D: AsRef<Path>>(mut self, dir: D) -> Self {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Specify the user (ID or name) to use.
     pub fn user<U>(mut self, user: U) -> Self
     where
         U: AsRef<OsStr>,
     {
-        let mut user_spec = OsString::from("--userspec=");
-        user_spec.push(user);
-        self.user_spec = Some(user_spec);
+        self.user = Some(user.as_ref().to_owned());
+        self.group = None;
         self
     }
 
@@ -68,11 +763,8 @@ impl Chroot {
         U: AsRef<OsStr>,
         G: AsRef<OsStr>,
     {
-        let mut user_spec = OsString::from("--userspec=");
-        user_spec.push(user);
-        user_spec.push(":");
-        user_spec.push(group);
-        self.user_spec = Some(user_spec);
+        self.user = Some(user.as_ref().to_owned());
+        self.group = Some(group.as_ref().to_owned());
         self
     }
 
@@ -82,54 +774,653 @@ impl Chroot {
         G: IntoIterator,
         G::Item: AsRef<OsStr>,
     {
-        let mut groups = groups.into_iter();
-
-        if let Some(group) = groups.next() {
-            // Only add the `groups` argument if the iterator is non-empty.
-            let mut arg = OsString::from("--groups=");
-            arg.push(group);
-
-            // Add remaining groups as a comma-separated list.
-            for group in groups {
-                arg.push(",");
-                arg.push(group);
-            }
+        let groups: Vec<OsString> = groups
+            .into_iter()
+            .map(|group| group.as_ref().to_owned())
+            .collect();
 
-            self.groups = Some(arg);
+        // Only specify groups if the iterator is non-empty.
+        if !groups.is_empty() {
+            self.groups = Some(groups);
         }
 
         self
     }
 
-    // Monomorphized form of `command` to reduce binary size.
-    fn command_impl(&self, root: &OsStr, program: &OsStr) -> Command {
-        let mut command = Command::new("chroot");
+    fn coreutils_command(&self, root: &OsStr, program: &OsStr) -> Command {
+        // Found before the environment policy changes `PATH`.
+        let search_path =
+            env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into());
+        let chroot = path::find_program(
+            Path::new("/"),
+            OsStr::new("chroot"),
+            &search_path,
+        )
+        .map_or_else(|| "chroot".into(), PathBuf::into_os_string);
+        let mut command = Command::new(chroot);
 
         if self.skip_chdir {
             command.arg("--skip-chdir");
         }
 
-        if let Some(user_spec) = &self.user_spec {
+        if let Some(user) = &self.user {
+            let mut user_spec = OsString::from("--userspec=");
+            user_spec.push(user);
+            if let Some(group) = &self.group {
+                user_spec.push(":");
+                user_spec.push(group);
+            }
             command.arg(user_spec);
         }
 
         if let Some(groups) = &self.groups {
-            command.arg(groups);
+            // Add groups as a comma-separated list.
+            let mut arg = OsString::from("--groups=");
+            for (i, group) in groups.iter().enumerate() {
+                if i != 0 {
+                    arg.push(",");
+                }
+                arg.push(group);
+            }
+            command.arg(arg);
         }
 
         command.arg(root);
         command.arg(program);
 
+        // The cgroup, descriptors and limits are inherited through
+        // `chroot(1)`.
+        let mut fds = Fds::new(&self.pass_fds);
+        let rlimits = self.rlimits.clone();
+        let cgroup = self.cgroup.is_some();
+
+        // SAFETY: `child::join_cgroup`, `Fds::apply` and `Rlimit::apply` only
+        // perform async-signal-safe operations.
+        unsafe {
+            command.pre_exec(move || {
+                child::report(|progress| {
+                    if cgroup {
+                        child::join_cgroup(progress)?;
+                    }
+                    progress.stage(Stage::Fds);
+                    fds.apply()?;
+                    progress.stage(Stage::Rlimit);
+                    for limit in &rlimits {
+                        progress.resource(limit.resource);
+                        limit.apply()?;
+                    }
+                    Ok(())
+                })
+            });
+        }
+
+        command
+    }
+
+    /// Returns the directories whose contents make up the root, from top to
+    /// bottom.
+    fn layers<'a>(&'a self, root: &'a Path) -> Vec<&'a Path> {
+        match &self.overlay {
+            Some(overlay) => overlay.layers().collect(),
+            None => vec![root],
+        }
+    }
+
+    fn resolve_ids(&self, root: &Path) -> Result<Ids, Error> {
+        if self.user.is_none() && self.groups.is_none() {
+            return Ok(Ids::default());
+        }
+        let layers = self.layers(root);
+        let passwd = Path::new("/etc/passwd");
+        let root = layers
+            .iter()
+            .find(|layer| {
+                path::resolve_in_root(layer, passwd)
+                    .is_ok_and(|passwd| passwd.exists())
+            })
+            .unwrap_or(&root);
+        let ids = IdResolver::from_root(root)?.resolve(
+            self.user.as_deref(),
+            self.group.as_deref(),
+            self.groups.as_deref(),
+        )?;
+        Ok(ids)
+    }
+
+    /// Checks that names in the user specification can be passed to
+    /// `chroot(1)` unambiguously.
+    fn check_user_spec(&self) -> Result<(), Error> {
+        let user_spec = self.user.iter().chain(&self.group);
+        let invalid = |name: &OsString, sep: &[u8]| {
+            name.is_empty() || name.as_bytes().iter().any(|b| sep.contains(b))
+        };
+
+        for name in user_spec {
+            if invalid(name, b":,") {
+                return Err(Error::InvalidUserSpec(name.clone()));
+            }
+        }
+        for name in self.groups.iter().flatten() {
+            if invalid(name, b",") {
+                return Err(Error::InvalidUserSpec(name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Performs the checks shared by all backends.
+    fn check(&self, root: &Path, program: &OsStr) -> Result<(), Error> {
+        match root.metadata() {
+            Ok(meta) if meta.is_dir() => {}
+            Ok(_) => return Err(Error::RootNotDirectory(root.to_owned())),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::RootNotFound(root.to_owned()));
+            }
+            Err(error) => return Err(error.into()),
+        }
+
+        let search_path =
+            self.env_var(OsStr::new("PATH")).unwrap_or_else(|| {
+                if self.inherit_env {
+                    DEFAULT_PATH.into()
+                } else {
+                    GUEST_PATH.into()
+                }
+            });
+        let found = self.layers(root).into_iter().any(|layer| {
+            path::find_program(layer, program, &search_path).is_some()
+        });
+        if !found {
+            return Err(Error::ProgramNotFound(program.to_owned()));
+        }
+
+        for limit in &self.rlimits {
+            // Capabilities in a user namespace do not apply to limits.
+            let raise = |limit: &Rlimit| -> io::Result<bool> {
+                Ok(limit.raises_hard()?
+                    && (self.unprivileged
+                        || !caps::has_effective(Capability::SysResource)?))
+            };
+            match raise(limit) {
+                Ok(false) => {}
+                Ok(true) => {
+                    let error = io::Error::from_raw_os_error(libc::EPERM);
+                    return Err(Error::Rlimit(limit.resource, error));
+                }
+                Err(error) => return Err(Error::Rlimit(limit.resource, error)),
+            }
+        }
+
+        // A new user namespace grants every capability within it.
+        if !self.unprivileged {
+            let needs_admin = self.backend == Backend::PivotRoot
+                || !self.mounts.is_empty()
+                || self.overlay.is_some()
+                || self.network != Network::Host
+                || self.pid_namespace
+                || self.hostname.is_some()
+                || self.domainname.is_some()
+                || self.ipc_namespace
+                || self.time_offsets.is_some();
+            if needs_admin && !caps::has_effective(Capability::SysAdmin)? {
+                return Err(Error::MissingCapability("CAP_SYS_ADMIN"));
+            }
+            let needs_chroot = self.backend != Backend::PivotRoot;
+            if needs_chroot && !caps::has_effective(Capability::SysChroot)? {
+                return Err(Error::MissingCapability("CAP_SYS_CHROOT"));
+            }
+        }
+
+        self.check_user_spec()
+    }
+
+    /// Checks that the configuration is supported by the backend.
+    fn check_backend(&self) -> Result<(), Error> {
+        for limit in &self.rlimits {
+            if limit.soft > limit.hard {
+                let error = io::Error::from_raw_os_error(libc::EINVAL);
+                return Err(Error::Rlimit(limit.resource, error));
+            }
+        }
+        if self.backend == Backend::PivotRoot && self.skip_chdir {
+            return Err(Error::Unsupported(
+                "skipping chdir with the pivot_root backend",
+            ));
+        }
+        if self.backend == Backend::Coreutils && self.current_dir.is_some() {
+            return Err(Error::Unsupported(
+                "a working directory with the coreutils backend",
+            ));
+        }
+        if self.backend == Backend::Coreutils && self.seccomp.is_some() {
+            return Err(Error::Unsupported(
+                "seccomp with the coreutils backend",
+            ));
+        }
+        if self.backend == Backend::Coreutils
+            && (!self.caps.is_empty() || self.no_new_privs)
+        {
+            return Err(Error::Unsupported(
+                "capabilities with the coreutils backend",
+            ));
+        }
+        if self.backend == Backend::Coreutils
+            && (!self.mounts.is_empty() || self.overlay.is_some())
+        {
+            return Err(Error::Unsupported(
+                "mounts with the coreutils backend",
+            ));
+        }
+        if self.unprivileged {
+            if self.backend == Backend::Coreutils {
+                return Err(Error::Unsupported(
+                    "user namespaces with the coreutils backend",
+                ));
+            }
+            if self.groups.is_some() {
+                return Err(Error::Unsupported(
+                    "supplementary groups in an unprivileged user namespace",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares everything the child needs, without changing anything
+    /// outside of the calling process.
+    fn prepare(&self, root: &Path) -> Result<Setup, Error> {
+        self.check_backend()?;
+
+        let mut ids = self.resolve_ids(root)?;
+
+        let user_namespace = if self.unprivileged {
+            // The mapping itself switches to the requested credentials.
+            let ids = mem::take(&mut ids);
+            Some(IdMaps::single(
+                ids.uid.unwrap_or(0),
+                unsafe { libc::geteuid() },
+                ids.gid.unwrap_or(0),
+                unsafe { libc::getegid() },
+            ))
+        } else {
+            None
+        };
+
+        let mut mounts = Vec::new();
+        let mut points = MountPoints::new(root, self.layers(root));
+        if let Some(overlay) = &self.overlay {
+            mounts.extend(overlay.prepare(root, self.unprivileged)?);
+            if overlay.is_transient() {
+                points.scratch(root.to_owned());
+            }
+        }
+        for mount in &self.mounts {
+            mounts.extend(mount.prepare(&mut points)?);
+        }
+
+        let seccomp = match &self.seccomp {
+            Some(filter) => {
+                let arch = Arch::native().ok_or(Error::Unsupported(
+                    "seccomp on this architecture",
+                ))?;
+                Some(filter.compile(arch)?)
+            }
+            None => None,
+        };
+
+        let landlock = match &self.landlock {
+            Some(ruleset) => Some(ruleset.prepare()?.0),
+            None => None,
+        };
+
+        let root = CString::new(root.as_os_str().as_bytes())
+            .map_err(|_| Error::RootNotFound(root.to_owned()))?;
+        let current_dir = if self.skip_chdir {
+            None
+        } else {
+            let dir = self.current_dir.as_deref().unwrap_or(Path::new("/"));
+            Some(mount::cstring(dir)?)
+        };
+
+        Ok(Setup {
+            root,
+            cgroup: self.cgroup.is_some(),
+            fds: Fds::new(&self.pass_fds),
+            current_dir,
+            ids,
+            user_namespace,
+            mounts,
+            pivot_root: self.backend == Backend::PivotRoot,
+            caps: self.caps,
+            no_new_privs: self.no_new_privs,
+            rlimits: self.rlimits.clone(),
+            network: self.network,
+            pid_namespace: self.pid_namespace,
+            landlock,
+            hostname: self.hostname.as_ref().map(|name| name.as_bytes().into()),
+            domainname: self
+                .domainname
+                .as_ref()
+                .map(|name| name.as_bytes().into()),
+            ipc_namespace: self.ipc_namespace,
+            time_offsets: self.time_offsets.map(|(monotonic, boottime)| {
+                format!(
+                    "monotonic {} {}\nboottime {} {}\n",
+                    monotonic.as_secs(),
+                    monotonic.subsec_nanos(),
+                    boottime.as_secs(),
+                    boottime.subsec_nanos(),
+                )
+                .into_bytes()
+            }),
+            seccomp,
+        })
+    }
+
+    /// Returns a `Command` for `program` that runs `setup` in the child, or
+    /// that fails to spawn if `setup` could not be prepared.
+    fn setup_command(program: &OsStr, setup: Result<Setup, Error>) -> Command {
+        // Failures are reported when the command is spawned.
+        let mut setup = setup.map_err(|error| error.raw_os_error());
+
+        let mut command = Command::new(program);
+
+        // SAFETY: `Setup::run` only performs async-signal-safe operations.
+        unsafe {
+            command.pre_exec(move || match &mut setup {
+                Ok(setup) => child::report(|progress| setup.run(progress)),
+                Err(errno) => Err(io::Error::from_raw_os_error(*errno)),
+            });
+        }
+
+        command
+    }
+
+    // Monomorphized form of `command` to reduce binary size.
+    fn command_impl(&self, root: &Path, program: &OsStr) -> Command {
+        let mut command = match self.backend {
+            Backend::Native | Backend::PivotRoot => {
+                Self::setup_command(program, self.prepare(root))
+            }
+            Backend::Coreutils => match self.check_backend() {
+                Ok(()) => self.coreutils_command(root.as_os_str(), program),
+                Err(error) => Self::setup_command(program, Err(error)),
+            },
+        };
+        self.apply_env(&mut command);
         command
     }
 
     /// Returns a `Command` suitable for spawning `program` with `root` as `/`.
+    ///
+    /// Errors in the configuration are reported when the command is spawned,
+    /// but only as the closest `errno`: even [`spawn`](Self::spawn) returns
+    /// them as [`Error::Io`]. Use [`try_command`](Self::try_command) to detect
+    /// them beforehand as typed errors, such as [`Error::Resolve`] for an
+    /// unknown user.
     #[inline]
     pub fn command<R, P>(&self, root: R, program: P) -> Command
     where
         R: AsRef<Path>,
         P: AsRef<OsStr>,
     {
-        self.command_impl(root.as_ref().as_os_str(), program.as_ref())
+        self.command_impl(root.as_ref(), program.as_ref())
+    }
+
+    // Monomorphized form of `try_command` to reduce binary size.
+    fn try_command_impl(
+        &self,
+        root: &Path,
+        program: &OsStr,
+    ) -> Result<Command, Error> {
+        self.check(root, program)?;
+
+        let mut command = match self.backend {
+            Backend::Native | Backend::PivotRoot => {
+                Self::setup_command(program, Ok(self.prepare(root)?))
+            }
+            Backend::Coreutils => {
+                self.check_backend()?;
+                self.coreutils_command(root.as_os_str(), program)
+            }
+        };
+        self.apply_env(&mut command);
+        Ok(command)
+    }
+
+    // Monomorphized form of `validate`.
+    fn validate_impl(&self, root: &Path, program: &OsStr) -> Result<(), Error> {
+        self.check(root, program)?;
+        match self.backend {
+            Backend::Native | Backend::PivotRoot => {
+                self.prepare(root).map(drop)
+            }
+            Backend::Coreutils => self.check_backend(),
+        }
+    }
+
+    /// Returns a `Command` suitable for spawning `program` with `root` as `/`,
+    /// or an error if it would fail to start.
+    ///
+    /// This checks that:
+    ///
+    /// - `root` is a directory.
+    ///
+    /// - `program` is an executable file within `root`, searching the
+    ///   command's `PATH` if it does not contain a slash.
+    ///
+    /// - The calling process has `CAP_SYS_CHROOT`, or `CAP_SYS_ADMIN` for
+    ///   mounts, namespaces and [`Backend::PivotRoot`], unless
+    ///   [`unprivileged`](Self::unprivileged) is used.
+    ///
+    /// - Resource limits are valid and do not raise a hard limit without
+    ///   `CAP_SYS_RESOURCE`.
+    ///
+    /// - The configuration is supported by the backend.
+    ///
+    /// - The user and groups are well-formed and, unless using
+    ///   [`Backend::Coreutils`], exist within `root`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # return;
+    /// match beach::Chroot::new().try_command("/path/to/root", "ls") {
+    ///     Ok(mut command) => {
+    ///         command.spawn().unwrap();
+    ///     }
+    ///     Err(error) => eprintln!("cannot run ls: {}", error),
+    /// }
+    /// ```
+    #[inline]
+    pub fn try_command<R, P>(
+        &self,
+        root: R,
+        program: P,
+    ) -> Result<Command, Error>
+    where
+        R: AsRef<Path>,
+        P: AsRef<OsStr>,
+    {
+        self.try_command_impl(root.as_ref(), program.as_ref())
+    }
+
+    /// Checks whether `program` can be run with `root` as `/`.
+    ///
+    /// See [`try_command`](Self::try_command) for the checks performed.
+    #[inline]
+    pub fn validate<R, P>(&self, root: R, program: P) -> Result<(), Error>
+    where
+        R: AsRef<Path>,
+        P: AsRef<OsStr>,
+    {
+        self.validate_impl(root.as_ref(), program.as_ref())
+    }
+
+    /// Spawns `command`, which must have been returned by
+    /// [`command`](Self::command) or [`try_command`](Self::try_command) on
+    /// this configuration.
+    ///
+    /// Unlike [`Command::spawn`], failures are reported precisely: a failed
+    /// step of the setup as [`Error::Setup`] with the path involved, and a
+    /// program that cannot be executed as [`Error::ProgramNotFound`]. Steps
+    /// with a dedicated error report it instead, such as
+    /// [`Error::MountSourceNotFound`] for a bind mount whose source was
+    /// removed after the command was created, [`Error::Cgroup`] and
+    /// [`Error::Rlimit`]. Errors in the configuration itself are only
+    /// detected this way by `try_command`.
+    ///
+    /// The returned [`Child`] reports how the program ended. See its
+    /// documentation for an example.
+    pub fn spawn(&self, command: &mut Command) -> Result<Child, Error> {
+        let min_fd = self.pass_fds.iter().map(|pass| pass.child + 1).max();
+        let cgroup = match &self.cgroup {
+            Some(cgroup) => Some(cgroup.create_unique()?),
+            None => None,
+        };
+        child::spawn(
+            command,
+            min_fd.unwrap_or(0).max(3),
+            cgroup,
+            self.seccomp.is_some(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::File,
+        io::Read,
+        os::unix::{
+            fs::PermissionsExt,
+            io::{AsRawFd, FromRawFd},
+        },
+    };
+
+    /// Returns the read and write ends of a pipe, away from the lowest
+    /// descriptors so that those stay free.
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        let high = |fd| unsafe {
+            let high = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 100);
+            libc::close(fd);
+            File::from_raw_fd(high)
+        };
+        unsafe {
+            assert_eq!(libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC), 0);
+        }
+        (high(fds[0]), high(fds[1]))
+    }
+
+    #[test]
+    fn validate_does_not_create_cgroup() {
+        let parent = env::temp_dir()
+            .join(format!("beach-validate-{}", std::process::id()));
+        std::fs::create_dir_all(&parent).unwrap();
+
+        let cgroup = Cgroup::new().parent(&parent).pids_max(16);
+        Chroot::new()
+            .unprivileged()
+            .cgroup(cgroup)
+            .validate("/", "sh")
+            .unwrap();
+        assert_eq!(std::fs::read_dir(&parent).unwrap().count(), 0);
+
+        std::fs::remove_dir(&parent).unwrap();
+    }
+
+    #[test]
+    fn missing_mount_point_is_not_created() {
+        let root = env::temp_dir()
+            .join(format!("beach-mount-point-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("tmp")).unwrap();
+        std::fs::write(root.join("sh"), "").unwrap();
+        std::fs::set_permissions(
+            root.join("sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        let missing = Chroot::new().bind("/", "/missing");
+        match missing.validate(&root, "/sh") {
+            Err(Error::MountPointNotFound(target)) => {
+                assert_eq!(target, Path::new("/missing"))
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(!root.join("missing").exists());
+
+        Chroot::new()
+            .tmpfs("/tmp", 0)
+            .bind("/", "/tmp/missing")
+            .validate(&root, "/sh")
+            .unwrap();
+        assert!(!root.join("tmp/missing").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn transient_overlay_rejects_lower_within_root() {
+        let dir = env::temp_dir()
+            .join(format!("beach-overlay-within-{}", std::process::id()));
+        let (root, base) = (dir.join("root"), dir.join("root/base"));
+        std::fs::create_dir_all(&base).unwrap();
+        for sh in &[root.join("sh"), base.join("sh")] {
+            std::fs::write(sh, "").unwrap();
+            let mode = std::fs::Permissions::from_mode(0o755);
+            std::fs::set_permissions(sh, mode).unwrap();
+        }
+
+        for lower in &[&root, &base] {
+            let overlay = Overlay::new(&[lower]);
+            let chroot = Chroot::new().overlay(overlay.clone());
+            match chroot.validate(&root, "/sh") {
+                Err(Error::Unsupported(_)) => {}
+                result => panic!("unexpected {:?}", result),
+            }
+
+            // Without a staging `tmpfs`, the lower directory stays visible.
+            let upper = overlay.upper(dir.join("upper"), dir.join("work"));
+            Chroot::new().overlay(upper).validate(&root, "/sh").unwrap();
+        }
+
+        let mount_point = dir.join("mnt");
+        std::fs::create_dir(&mount_point).unwrap();
+        let outside = Chroot::new().overlay(Overlay::new(&[&base]));
+        outside.validate(&mount_point, "/sh").unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn passed_fds_do_not_replace_exec_error_pipe() {
+        // The lowest free descriptors, where `Command` would otherwise open
+        // its pipe, as in `--jobserver-auth=3,4`.
+        let pipes: Vec<_> = (0..3).map(|_| pipe()).collect();
+        let mut chroot = Chroot::new().unprivileged();
+        for (fd, (_, write)) in (3..).zip(&pipes) {
+            chroot = chroot.pass_fd(write.as_raw_fd(), fd);
+        }
+
+        let mut command = chroot.command("/", "/nonexistent");
+        match chroot.spawn(&mut command) {
+            Err(Error::ProgramNotFound(_)) => {}
+            result => panic!("unexpected result {:?}", result.map(drop)),
+        }
+
+        let mut command = chroot.command("/", "sh");
+        command.args(["-c", "echo 3 >&3; echo 4 >&4; echo 5 >&5"]);
+        let mut child = chroot.spawn(&mut command).unwrap();
+        assert!(child.wait().unwrap().success());
+
+        for (fd, (mut read, write)) in (3..).zip(pipes) {
+            drop(write);
+            let mut output = String::new();
+            read.read_to_string(&mut output).unwrap();
+            assert_eq!(output, format!("{}\n", fd));
+        }
     }
 }
@@ -0,0 +1,385 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    ffi::OsString,
+    fs::File,
+    io,
+    os::unix::{ffi::OsStringExt, fs::FileExt},
+    path::{Path, PathBuf},
+};
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_STRSZ: u64 = 10;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+/// The largest table read from a file, which bounds allocations for
+/// malformed files.
+const MAX_TABLE: u64 = 16 << 20;
+
+/// The parts of an ELF file that determine what it loads.
+#[derive(Debug, Default)]
+pub(crate) struct Elf {
+    pub class64: bool,
+    pub machine: u16,
+    /// The dynamic loader, from `PT_INTERP`.
+    pub interp: Option<PathBuf>,
+    /// The `DT_NEEDED` libraries.
+    pub needed: Vec<String>,
+    pub rpath: Option<String>,
+    pub runpath: Option<String>,
+}
+
+/// Reads integers of the file's class and byte order.
+struct Reader<'a> {
+    file: &'a File,
+    class64: bool,
+    little: bool,
+}
+
+impl Reader<'_> {
+    fn bytes(&self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let end = offset.checked_add(len).map(i64::try_from);
+        if len > MAX_TABLE || !matches!(end, Some(Ok(_))) {
+            return Err(malformed());
+        }
+        let mut buf = vec![0; len as usize];
+        match self.file.read_exact_at(&mut buf, offset) {
+            Ok(()) => Ok(buf),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(malformed())
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    fn u16(&self, buf: &[u8], at: usize) -> io::Result<u16> {
+        let bytes = field(buf, at, 2)?.try_into().unwrap();
+        Ok(if self.little {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, buf: &[u8], at: usize) -> io::Result<u32> {
+        let bytes = field(buf, at, 4)?.try_into().unwrap();
+        Ok(if self.little {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, buf: &[u8], at: usize) -> io::Result<u64> {
+        let bytes = field(buf, at, 8)?.try_into().unwrap();
+        Ok(if self.little {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    /// Reads an address, offset or size, whose width depends on the class.
+    fn word(&self, buf: &[u8], at: usize) -> io::Result<u64> {
+        if self.class64 {
+            self.u64(buf, at)
+        } else {
+            self.u32(buf, at).map(u64::from)
+        }
+    }
+}
+
+struct Segment {
+    kind: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+impl Elf {
+    /// Parses the ELF file at `path`, or returns `None` if it is not one.
+    pub fn read(path: &Path) -> io::Result<Option<Self>> {
+        let file = File::open(path)?;
+        let mut ident = [0; 64];
+        let len = file.read_at(&mut ident, 0)?;
+        if len < 16 || ident[..4] != *b"\x7fELF" {
+            return Ok(None);
+        }
+
+        let reader = Reader {
+            file: &file,
+            class64: match ident[4] {
+                1 => false,
+                2 => true,
+                _ => return Err(malformed()),
+            },
+            little: match ident[5] {
+                1 => true,
+                2 => false,
+                _ => return Err(malformed()),
+            },
+        };
+        let header = &ident[..len];
+        let (phoff, phentsize, phnum) = if reader.class64 {
+            (
+                reader.u64(header, 32)?,
+                reader.u16(header, 54)?,
+                reader.u16(header, 56)?,
+            )
+        } else {
+            (
+                reader.u32(header, 28)?.into(),
+                reader.u16(header, 42)?,
+                reader.u16(header, 44)?,
+            )
+        };
+
+        let table =
+            reader.bytes(phoff, u64::from(phentsize) * u64::from(phnum))?;
+        let mut segments = Vec::with_capacity(phnum.into());
+        for i in 0..usize::from(phnum) {
+            let at = i
+                .checked_mul(usize::from(phentsize))
+                .ok_or_else(malformed)?;
+            let entry = table.get(at..).ok_or_else(malformed)?;
+            let (offset, vaddr, filesz) = if reader.class64 {
+                (8, 16, 32)
+            } else {
+                (4, 8, 16)
+            };
+            segments.push(Segment {
+                kind: reader.u32(entry, 0)?,
+                offset: reader.word(entry, offset)?,
+                vaddr: reader.word(entry, vaddr)?,
+                filesz: reader.word(entry, filesz)?,
+            });
+        }
+
+        let mut elf = Self {
+            class64: reader.class64,
+            machine: reader.u16(header, 18)?,
+            ..Self::default()
+        };
+        for segment in &segments {
+            match segment.kind {
+                PT_INTERP => {
+                    let mut interp =
+                        reader.bytes(segment.offset, segment.filesz)?;
+                    if let Some(nul) = interp.iter().position(|&b| b == 0) {
+                        interp.truncate(nul);
+                    }
+                    elf.interp = Some(path_from(interp));
+                }
+                PT_DYNAMIC => elf.dynamic(&reader, &segments, segment)?,
+                _ => {}
+            }
+        }
+        Ok(Some(elf))
+    }
+
+    /// Reads the libraries and search paths from the dynamic section.
+    fn dynamic(
+        &mut self,
+        reader: &Reader,
+        segments: &[Segment],
+        dynamic: &Segment,
+    ) -> io::Result<()> {
+        let table = reader.bytes(dynamic.offset, dynamic.filesz)?;
+        let size = if reader.class64 { 16 } else { 8 };
+
+        let mut entries = Vec::new();
+        let (mut strtab, mut strsz) = (None, 0);
+        for at in (0..table.len() / size).map(|i| i * size) {
+            let tag = reader.word(&table, at)?;
+            let value = reader.word(&table, at + size / 2)?;
+            match tag {
+                DT_NULL => break,
+                DT_STRTAB => strtab = Some(value),
+                DT_STRSZ => strsz = value,
+                DT_NEEDED | DT_RPATH | DT_RUNPATH => entries.push((tag, value)),
+                _ => {}
+            }
+        }
+        let strtab = match strtab {
+            Some(strtab) => strtab,
+            None if entries.is_empty() => return Ok(()),
+            None => return Err(malformed()),
+        };
+
+        // `DT_STRTAB` is an address, so find where it is loaded from.
+        let offset = segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD)
+            .find(|segment| {
+                strtab >= segment.vaddr
+                    && strtab - segment.vaddr < segment.filesz
+            })
+            .and_then(|segment| {
+                (strtab - segment.vaddr).checked_add(segment.offset)
+            })
+            .ok_or_else(malformed)?;
+        let strings = reader.bytes(offset, strsz)?;
+
+        for (tag, value) in entries {
+            let string = usize::try_from(value)
+                .ok()
+                .and_then(|value| strings.get(value..))
+                .ok_or_else(malformed)?;
+            let end =
+                string.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+            let string = String::from_utf8_lossy(&string[..end]).into_owned();
+            match tag {
+                DT_NEEDED => self.needed.push(string),
+                DT_RPATH => self.rpath = Some(string),
+                _ => self.runpath = Some(string),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether a library can be loaded along with `self`.
+    pub fn is_compatible(&self, library: &Self) -> bool {
+        self.class64 == library.class64 && self.machine == library.machine
+    }
+}
+
+fn path_from(bytes: Vec<u8>) -> PathBuf {
+    OsString::from_vec(bytes).into()
+}
+
+/// Returns the `len` bytes of `buf` at `at`.
+fn field(buf: &[u8], at: usize, len: usize) -> io::Result<&[u8]> {
+    at.checked_add(len)
+        .and_then(|end| buf.get(at..end))
+        .ok_or_else(malformed)
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed ELF file")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    /// Returns a little-endian ELF64 file for x86-64 with the given program
+    /// headers, as type, offset, address and size, followed by `data`.
+    fn elf64(segments: &[(u32, u64, u64, u64)], data: &[u8]) -> Vec<u8> {
+        let mut file = vec![0; 64];
+        file[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+        file[18..20].copy_from_slice(&62u16.to_le_bytes());
+        file[32..40].copy_from_slice(&64u64.to_le_bytes());
+        file[54..56].copy_from_slice(&56u16.to_le_bytes());
+        file[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        for &(kind, offset, vaddr, size) in segments {
+            let mut header = [0; 56];
+            header[..4].copy_from_slice(&kind.to_le_bytes());
+            header[8..16].copy_from_slice(&offset.to_le_bytes());
+            header[16..24].copy_from_slice(&vaddr.to_le_bytes());
+            header[32..40].copy_from_slice(&size.to_le_bytes());
+            file.extend_from_slice(&header);
+        }
+        file.extend_from_slice(data);
+        file
+    }
+
+    fn read(name: &str, contents: &[u8]) -> io::Result<Option<Elf>> {
+        let path = env::temp_dir().join(format!(
+            "beach-elf-{}-{}",
+            name,
+            process::id()
+        ));
+        fs::write(&path, contents).unwrap();
+        let elf = Elf::read(&path);
+        fs::remove_file(&path).unwrap();
+        elf
+    }
+
+    fn is_malformed(result: io::Result<Option<Elf>>) -> bool {
+        result.is_err_and(|error| error.kind() == io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn dynamic_section_is_read() {
+        let start = 64 + 3 * 56;
+        let interp = b"/lib/ld.so\0";
+        let strings = b"\0libc.so.6\0$ORIGIN\0";
+        let dynamic_at = start + interp.len() as u64;
+        let strings_at = dynamic_at + 5 * 16;
+        let mut data = interp.to_vec();
+        for &(tag, value) in &[
+            (DT_STRTAB, 0x1000 + strings_at),
+            (DT_STRSZ, strings.len() as u64),
+            (DT_NEEDED, 1),
+            (DT_RUNPATH, 11),
+            (DT_NULL, 0),
+        ] {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(strings);
+        let len = start + data.len() as u64;
+        let file = elf64(
+            &[
+                (PT_INTERP, start, 0, interp.len() as u64),
+                (PT_LOAD, 0, 0x1000, len),
+                (PT_DYNAMIC, dynamic_at, 0, 5 * 16),
+            ],
+            &data,
+        );
+
+        let elf = read("dynamic", &file).unwrap().unwrap();
+        assert!(elf.class64);
+        assert_eq!(elf.machine, 62);
+        assert_eq!(elf.interp.as_deref(), Some(Path::new("/lib/ld.so")));
+        assert_eq!(elf.needed, ["libc.so.6"]);
+        assert_eq!(elf.runpath.as_deref(), Some("$ORIGIN"));
+        assert_eq!(elf.rpath, None);
+    }
+
+    #[test]
+    fn other_files_are_not_elf() {
+        assert!(read("script", b"#!/bin/sh\n").unwrap().is_none());
+        assert!(read("short", b"\x7fELF").unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_headers_are_malformed() {
+        let file = elf64(&[(PT_LOAD, 0, 0, 0)], &[]);
+        assert!(is_malformed(read("header", &file[..40])));
+        assert!(is_malformed(read("segments", &file[..100])));
+
+        let mut class = file.clone();
+        class[4] = 3;
+        assert!(is_malformed(read("class", &class)));
+    }
+
+    #[test]
+    fn out_of_range_offsets_are_malformed() {
+        let file = elf64(&[(PT_INTERP, u64::MAX, 0, 16)], &[]);
+        assert!(is_malformed(read("interp", &file)));
+
+        // The string table's address is within a segment whose offset
+        // overflows once added.
+        let mut dynamic = Vec::new();
+        for &(tag, value) in &[(DT_STRTAB, 16u64), (DT_NEEDED, 0)] {
+            dynamic.extend_from_slice(&tag.to_le_bytes());
+            dynamic.extend_from_slice(&value.to_le_bytes());
+        }
+        let file = elf64(
+            &[
+                (PT_LOAD, u64::MAX, 0, u64::MAX),
+                (PT_DYNAMIC, 64 + 2 * 56, 0, 32),
+            ],
+            &dynamic,
+        );
+        assert!(is_malformed(read("strtab", &file)));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{ResolveError, Resource, Stage};
+use std::{error, ffi::OsString, fmt, io, path::PathBuf};
+
+/// An error returned when a sandboxed command cannot be set up.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The root directory does not exist.
+    RootNotFound(PathBuf),
+    /// The root path exists but is not a directory.
+    RootNotDirectory(PathBuf),
+    /// The program could not be found or is not executable within the root
+    /// directory.
+    ProgramNotFound(OsString),
+    /// The source of a mount does not exist.
+    MountSourceNotFound(PathBuf),
+    /// The target of a mount does not exist within the root directory.
+    MountPointNotFound(PathBuf),
+    /// The calling process lacks a capability required to run the command.
+    MissingCapability(&'static str),
+    /// The configuration requires a feature that is not supported.
+    Unsupported(&'static str),
+    /// A seccomp rule names a system call that does not exist.
+    UnknownSyscall(String),
+    /// A seccomp filter exceeds the limits of classic BPF.
+    FilterTooLarge,
+    /// A user or group specification is malformed.
+    InvalidUserSpec(OsString),
+    /// A resource limit is invalid or cannot be applied.
+    Rlimit(Resource, io::Error),
+    /// A cgroup file cannot be read or written.
+    Cgroup(PathBuf, io::Error),
+    /// A user or group could not be resolved.
+    Resolve(ResolveError),
+    /// A step of the setup failed within the child, as reported by
+    /// [`Chroot::spawn`](crate::Chroot::spawn), along with the path it
+    /// operated on, if any.
+    Setup(Stage, Option<PathBuf>, io::Error),
+    /// An I/O error occurred.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::RootNotFound(path) => {
+                write!(f, "root directory {:?} does not exist", path)
+            }
+            Self::RootNotDirectory(path) => {
+                write!(f, "root {:?} is not a directory", path)
+            }
+            Self::ProgramNotFound(program) => {
+                write!(f, "program {:?} not found within root", program)
+            }
+            Self::MountSourceNotFound(path) => {
+                write!(f, "mount source {:?} does not exist", path)
+            }
+            Self::MountPointNotFound(path) => {
+                write!(f, "mount point {:?} does not exist within root", path)
+            }
+            Self::MissingCapability(cap) => {
+                write!(f, "missing capability {}", cap)
+            }
+            Self::Unsupported(feature) => {
+                write!(f, "unsupported: {}", feature)
+            }
+            Self::UnknownSyscall(name) => {
+                write!(f, "unknown system call {:?}", name)
+            }
+            Self::FilterTooLarge => f.write_str("seccomp filter is too large"),
+            Self::InvalidUserSpec(spec) => {
+                write!(f, "invalid user or group {:?}", spec)
+            }
+            Self::Rlimit(resource, error) => {
+                write!(f, "cannot set {}: {}", resource, error)
+            }
+            Self::Cgroup(path, error) => {
+                write!(f, "cgroup file {:?}: {}", path, error)
+            }
+            Self::Resolve(error) => error.fmt(f),
+            Self::Setup(stage, None, error) => {
+                write!(f, "failed {}: {}", stage, error)
+            }
+            Self::Setup(stage, Some(path), error) => {
+                write!(f, "failed {} ({:?}): {}", stage, path, error)
+            }
+            Self::Io(error) => error.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Rlimit(_, error)
+            | Self::Cgroup(_, error)
+            | Self::Setup(_, _, error) => Some(error),
+            Self::Resolve(error) => Some(error),
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<ResolveError> for Error {
+    #[inline]
+    fn from(error: ResolveError) -> Self {
+        Self::Resolve(error)
+    }
+}
+
+impl Error {
+    /// The closest `errno` value, used when the error can only be reported
+    /// from within the child process.
+    pub(crate) fn raw_os_error(&self) -> i32 {
+        match self {
+            Self::RootNotFound(_)
+            | Self::ProgramNotFound(_)
+            | Self::MountSourceNotFound(_)
+            | Self::MountPointNotFound(_) => libc::ENOENT,
+            Self::RootNotDirectory(_) => libc::ENOTDIR,
+            Self::MissingCapability(_) => libc::EPERM,
+            Self::Unsupported(_) => libc::ENOTSUP,
+            Self::UnknownSyscall(_)
+            | Self::FilterTooLarge
+            | Self::InvalidUserSpec(_)
+            | Self::Resolve(_) => libc::EINVAL,
+            Self::Rlimit(_, error)
+            | Self::Cgroup(_, error)
+            | Self::Setup(_, _, error)
+            | Self::Io(error) => error.raw_os_error().unwrap_or(libc::EIO),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::setup::cvt;
+use std::{io, mem, os::unix::io::RawFd};
+
+/// `CLOSE_RANGE_CLOEXEC` from `linux/close_range.h`.
+const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+
+/// A descriptor of the calling process that the command receives as `child`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PassFd {
+    pub host: RawFd,
+    pub child: RawFd,
+}
+
+/// The descriptors the command receives, prepared in the parent so that they
+/// can be arranged in the child without allocating.
+#[derive(Clone, Debug)]
+pub(crate) struct Fds {
+    pass: Vec<PassFd>,
+    /// Where each passed descriptor is moved to before being renumbered.
+    temps: Vec<RawFd>,
+}
+
+impl Fds {
+    pub fn new(pass: &[PassFd]) -> Self {
+        Self {
+            pass: pass.to_vec(),
+            temps: vec![-1; pass.len()],
+        }
+    }
+
+    /// Leaves only stdin, stdout, stderr and the passed descriptors open once
+    /// the program is executed.
+    ///
+    /// Other descriptors are marked close-on-exec rather than closed, since
+    /// `Command` reports `exec` failures through one of them.
+    ///
+    /// Only async-signal-safe operations are used.
+    pub fn apply(&mut self) -> io::Result<()> {
+        unsafe {
+            cloexec_from(3);
+
+            // Move every passed descriptor above every target first, so that
+            // renumbering one cannot replace another.
+            let min = self.pass.iter().map(|pass| pass.child + 1).max();
+            let min = min.unwrap_or(0).max(3);
+            for (pass, temp) in self.pass.iter().zip(&mut self.temps) {
+                *temp =
+                    cvt(libc::fcntl(pass.host, libc::F_DUPFD_CLOEXEC, min))?;
+            }
+
+            // The new descriptors are not close-on-exec.
+            for (pass, &temp) in self.pass.iter().zip(&self.temps) {
+                cvt(libc::dup2(temp, pass.child))?;
+                libc::close(temp);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Marks every descriptor from `first` onwards close-on-exec.
+unsafe fn cloexec_from(first: RawFd) {
+    let ret = libc::syscall(
+        libc::SYS_close_range,
+        first as libc::c_uint,
+        libc::c_uint::MAX,
+        CLOSE_RANGE_CLOEXEC,
+    );
+    if ret == 0 {
+        return;
+    }
+
+    // `CLOSE_RANGE_CLOEXEC` requires Linux 5.11.
+    for fd in first..open_max() {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+}
+
+/// Closes every descriptor from 3 onwards except `keep`.
+pub(crate) unsafe fn close_all_except(keep: RawFd) {
+    let close_range = |first: libc::c_uint, last: libc::c_uint| {
+        libc::syscall(libc::SYS_close_range, first, last, 0) == 0
+    };
+    let keep_range = keep as libc::c_uint;
+    let below = keep == 3 || close_range(3, keep_range - 1);
+    if below && close_range(keep_range + 1, libc::c_uint::MAX) {
+        return;
+    }
+
+    // `close_range(2)` requires Linux 5.9.
+    for fd in 3..open_max() {
+        if fd != keep {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Returns an upper bound on the descriptors that may be open.
+unsafe fn open_max() -> RawFd {
+    let mut limit: libc::rlimit = mem::zeroed();
+    if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) == 0 {
+        limit.rlim_cur.min(1 << 20) as RawFd
+    } else {
+        1024
+    }
+}
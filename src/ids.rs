@@ -0,0 +1,385 @@
+use crate::path;
+use std::{
+    error::Error,
+    ffi::{OsStr, OsString},
+    fmt, fs, io,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+/// Numeric credentials that a sandboxed process switches to.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ids {
+    /// The user ID, if it should be changed.
+    pub uid: Option<libc::uid_t>,
+    /// The group ID, if it should be changed.
+    pub gid: Option<libc::gid_t>,
+    /// The supplementary group IDs, if they should be changed.
+    pub groups: Option<Vec<libc::gid_t>>,
+}
+
+/// Resolves user and group names using the `/etc/passwd` and `/etc/group`
+/// files of a root directory.
+///
+/// A missing database is treated as empty, so numeric IDs can always be used.
+///
+/// # Examples
+///
+/// ```
+/// # return;
+/// let resolver = beach::IdResolver::from_root("/path/to/root").unwrap();
+/// let ids = resolver.resolve(Some("build".as_ref()), None, None).unwrap();
+/// println!("{:?}", ids.uid);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct IdResolver {
+    users: Vec<User>,
+    groups: Vec<Group>,
+}
+
+#[derive(Clone, Debug)]
+struct User {
+    name: OsString,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+#[derive(Clone, Debug)]
+struct Group {
+    name: OsString,
+    gid: libc::gid_t,
+    members: Vec<OsString>,
+}
+
+impl IdResolver {
+    /// Reads the user and group databases under `root`.
+    ///
+    /// Symbolic links are resolved within `root`, so a link to `/etc` does
+    /// not lead to the host's databases.
+    pub fn from_root<R: AsRef<Path>>(root: R) -> io::Result<Self> {
+        let root = root.as_ref();
+        let passwd = read_db(root, "/etc/passwd")?;
+        let group = read_db(root, "/etc/group")?;
+        Ok(Self::parse(&passwd, &group))
+    }
+
+    /// Parses the contents of `passwd(5)` and `group(5)` files.
+    ///
+    /// Malformed lines are ignored.
+    pub fn parse(passwd: &[u8], group: &[u8]) -> Self {
+        let users = fields(passwd)
+            .filter_map(|fields| {
+                Some(User {
+                    name: os(fields.first()?),
+                    uid: parse_id(fields.get(2)?)?,
+                    gid: parse_id(fields.get(3)?)?,
+                })
+            })
+            .collect();
+
+        let groups = fields(group)
+            .filter_map(|fields| {
+                Some(Group {
+                    name: os(fields.first()?),
+                    gid: parse_id(fields.get(2)?)?,
+                    members: fields
+                        .get(3)
+                        .map(|members| {
+                            members
+                                .split(|&b| b == b',')
+                                .filter(|member| !member.is_empty())
+                                .map(os)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                })
+            })
+            .collect();
+
+        Self { users, groups }
+    }
+
+    /// Resolves `user`, `group` and supplementary `groups`, each of which may
+    /// be a name or a numeric ID.
+    ///
+    /// This follows the rules of `chroot --userspec`:
+    ///
+    /// - If `group` is not given, the primary group of `user` is used.
+    ///
+    /// - If `groups` is not given and `user` is a name, the supplementary
+    ///   groups are those that list `user` as a member, plus its primary
+    ///   group. If `user` is a numeric ID, supplementary groups are dropped.
+    pub fn resolve(
+        &self,
+        user: Option<&OsStr>,
+        group: Option<&OsStr>,
+        groups: Option<&[OsString]>,
+    ) -> Result<Ids, ResolveError> {
+        let mut ids = Ids::default();
+        let mut user_name = None;
+
+        if let Some(user) = user {
+            let entry = match parse_id(user.as_bytes()) {
+                Some(uid) => {
+                    ids.uid = Some(uid);
+                    self.users.iter().find(|entry| entry.uid == uid)
+                }
+                None => {
+                    let entry = self
+                        .users
+                        .iter()
+                        .find(|entry| entry.name == user)
+                        .ok_or_else(|| {
+                            ResolveError::UnknownUser(user.into())
+                        })?;
+                    ids.uid = Some(entry.uid);
+                    user_name = Some(user);
+                    Some(entry)
+                }
+            };
+            ids.gid = entry.map(|entry| entry.gid);
+        }
+
+        if let Some(group) = group {
+            ids.gid = Some(self.group(group)?);
+        } else if let (Some(uid), None) = (ids.uid, ids.gid) {
+            return Err(ResolveError::NoGroup(uid));
+        }
+
+        if let Some(groups) = groups {
+            let groups = groups
+                .iter()
+                .map(|group| self.group(group))
+                .collect::<Result<_, _>>()?;
+            ids.groups = Some(groups);
+        } else if let (Some(name), Some(gid)) = (user_name, ids.gid) {
+            let mut groups = vec![gid];
+            for group in &self.groups {
+                let is_member = group.members.iter().any(|m| m == name);
+                if is_member && !groups.contains(&group.gid) {
+                    groups.push(group.gid);
+                }
+            }
+            ids.groups = Some(groups);
+        } else if ids.uid.is_some() {
+            ids.groups = Some(Vec::new());
+        }
+
+        Ok(ids)
+    }
+
+    fn group(&self, group: &OsStr) -> Result<libc::gid_t, ResolveError> {
+        if let Some(gid) = parse_id(group.as_bytes()) {
+            return Ok(gid);
+        }
+        self.groups
+            .iter()
+            .find(|entry| entry.name == group)
+            .map(|entry| entry.gid)
+            .ok_or_else(|| ResolveError::UnknownGroup(group.into()))
+    }
+}
+
+/// An error returned when resolving user and group names fails.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResolveError {
+    /// The user name does not exist in `/etc/passwd`.
+    UnknownUser(OsString),
+    /// The group name does not exist in `/etc/group`.
+    UnknownGroup(OsString),
+    /// No group was given for a user ID that does not exist in `/etc/passwd`.
+    NoGroup(libc::uid_t),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownUser(user) => {
+                write!(f, "user {:?} not found in /etc/passwd", user)
+            }
+            Self::UnknownGroup(group) => {
+                write!(f, "group {:?} not found in /etc/group", group)
+            }
+            Self::NoGroup(uid) => {
+                write!(f, "no group specified for unknown uid {}", uid)
+            }
+        }
+    }
+}
+
+impl Error for ResolveError {}
+
+fn read_db(root: &Path, path: &str) -> io::Result<Vec<u8>> {
+    let path = path::resolve_in_root(root, Path::new(path))?;
+    match fs::read(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        result => result,
+    }
+}
+
+/// Splits a colon-separated database into the fields of each entry.
+fn fields(db: &[u8]) -> impl Iterator<Item = Vec<&[u8]>> {
+    db.split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+        .map(|line| line.split(|&b| b == b':').collect())
+}
+
+fn os(bytes: &[u8]) -> OsString {
+    OsStr::from_bytes(bytes).to_owned()
+}
+
+/// Parses a numeric ID, as accepted by `chroot --userspec`.
+fn parse_id(s: &[u8]) -> Option<u32> {
+    let s = s.strip_prefix(b"+").unwrap_or(s);
+    if s.is_empty() || !s.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(s).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, os::unix::fs::symlink, process};
+
+    const PASSWD: &[u8] = b"\
+# comment
+
+root:x:0:0:root:/root:/bin/sh
+build:x:1000:1000::/build:/bin/sh
+build:x:2000:2000:duplicate:/:/bin/sh
+short:x:3000
+letters:x:abc:1:::
+huge:x:4294967296:1:::
+plus:x:+42:+43:::
+empty:x::1:::
+nogroup:x:1001:1001::/:/bin/sh";
+
+    const GROUP: &[u8] = b"\
+root:x:0:
+build:x:1000:
+wheel:x:10:root,,build,
+audio:x:63:build
+nomembers:x:64
+bad:x:-1:build
+1000:x:7:";
+
+    fn resolve(
+        user: Option<&str>,
+        group: Option<&str>,
+        groups: Option<&[&str]>,
+    ) -> Result<Ids, ResolveError> {
+        let resolver = IdResolver::parse(PASSWD, GROUP);
+        let groups: Option<Vec<OsString>> =
+            groups.map(|groups| groups.iter().map(OsString::from).collect());
+        resolver.resolve(
+            user.map(OsStr::new),
+            group.map(OsStr::new),
+            groups.as_deref(),
+        )
+    }
+
+    fn ids(uid: u32, gid: u32, groups: &[u32]) -> Ids {
+        Ids {
+            uid: Some(uid),
+            gid: Some(gid),
+            groups: Some(groups.to_vec()),
+        }
+    }
+
+    #[test]
+    fn names_resolve_with_member_groups() {
+        assert_eq!(
+            resolve(Some("build"), None, None),
+            Ok(ids(1000, 1000, &[1000, 10, 63]))
+        );
+        assert_eq!(resolve(Some("root"), None, None), Ok(ids(0, 0, &[0, 10])));
+        // The last entry has no trailing newline.
+        assert_eq!(
+            resolve(Some("nogroup"), None, None),
+            Ok(ids(1001, 1001, &[1001]))
+        );
+        assert_eq!(resolve(Some("plus"), None, None), Ok(ids(42, 43, &[43])));
+    }
+
+    #[test]
+    fn malformed_entries_are_ignored() {
+        for user in &["short", "letters", "huge", "empty", "# comment", ""] {
+            assert_eq!(
+                resolve(Some(user), None, None),
+                Err(ResolveError::UnknownUser(user.into())),
+            );
+        }
+        assert_eq!(
+            resolve(None, Some("bad"), None),
+            Err(ResolveError::UnknownGroup("bad".into())),
+        );
+        // A missing member list is an empty one.
+        assert_eq!(
+            resolve(None, Some("nomembers"), None).unwrap().gid,
+            Some(64)
+        );
+    }
+
+    #[test]
+    fn numeric_ids_are_not_looked_up_by_name() {
+        // The group named 1000 is not used, and neither are supplementary
+        // groups of a user given by ID.
+        assert_eq!(
+            resolve(Some("1000"), Some("1000"), None),
+            Ok(ids(1000, 1000, &[]))
+        );
+        assert_eq!(resolve(Some("+0"), None, None), Ok(ids(0, 0, &[])));
+        assert_eq!(
+            resolve(Some("5000"), None, None),
+            Err(ResolveError::NoGroup(5000))
+        );
+        assert_eq!(
+            resolve(Some("5000"), Some("audio"), None),
+            Ok(ids(5000, 63, &[]))
+        );
+        assert_eq!(
+            resolve(Some("+"), None, None),
+            Err(ResolveError::UnknownUser("+".into())),
+        );
+    }
+
+    #[test]
+    fn explicit_groups_replace_member_groups() {
+        assert_eq!(
+            resolve(Some("build"), Some("wheel"), Some(&["audio", "7"])),
+            Ok(ids(1000, 10, &[63, 7])),
+        );
+        assert_eq!(
+            resolve(Some("build"), None, Some(&[])),
+            Ok(ids(1000, 1000, &[])),
+        );
+        assert_eq!(
+            resolve(None, None, Some(&["video"])),
+            Err(ResolveError::UnknownGroup("video".into())),
+        );
+        assert_eq!(resolve(None, None, None), Ok(Ids::default()));
+    }
+
+    #[test]
+    fn from_root_resolves_links_within_root() {
+        let root =
+            env::temp_dir().join(format!("beach-ids-{}-links", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::create_dir_all(root.join("data")).unwrap();
+
+        // Absolute links that would lead to the host's databases.
+        fs::write(root.join("data/passwd"), "build:x:1234:1234::/:/bin/sh\n")
+            .unwrap();
+        symlink("/data/passwd", root.join("etc/passwd")).unwrap();
+        symlink("/nonexistent/group", root.join("etc/group")).unwrap();
+
+        let resolver = IdResolver::from_root(&root).unwrap();
+        let ids = resolver.resolve(Some("build".as_ref()), None, None);
+        assert_eq!(ids.unwrap().uid, Some(1234));
+        assert!(resolver.resolve(Some("root".as_ref()), None, None).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
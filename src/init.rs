@@ -0,0 +1,176 @@
+//! A minimal init for PID namespaces.
+//!
+//! Three processes are involved. The process spawned by `Command` enters the
+//! namespace with `unshare(2)`, which only affects its children, and forks
+//! the init. It stays outside the namespace, forwarding signals to the init
+//! and exiting like the program once the init reports how it ended.
+//!
+//! The init becomes PID 1, finishes the setup and forks once more right
+//! before `exec`. It then forwards signals to the program and reaps every
+//! process orphaned in the namespace. When the program exits, so does the
+//! init, and the kernel kills whatever is left in the namespace.
+
+use crate::{fd, setup::cvt};
+use std::{
+    io, mem, ptr,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// Signals forwarded to the program.
+const FORWARDED: &[libc::c_int] = &[
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+    libc::SIGALRM,
+    libc::SIGWINCH,
+    libc::SIGCONT,
+];
+
+/// The process that signals are forwarded to, or 0 if there is none yet.
+static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+/// The pipe that the init reports the wait status of the program over.
+static STATUS_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn forward(signal: libc::c_int) {
+    let pid = FORWARD_TO.load(Ordering::Relaxed);
+    if pid > 0 {
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+/// Enters a new PID namespace and forks the init, returning only within it.
+///
+/// Only async-signal-safe operations are used.
+pub(crate) fn enter_namespace() -> io::Result<()> {
+    unsafe {
+        cvt(libc::unshare(libc::CLONE_NEWPID))?;
+
+        let mut fds = [0; 2];
+        cvt(libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC))?;
+        let [read_fd, write_fd] = fds;
+
+        // Handlers are installed first so that they are inherited by the
+        // init, which only receives signals it handles from outside.
+        for &signal in FORWARDED {
+            let mut action: libc::sigaction = mem::zeroed();
+            let handler: extern "C" fn(libc::c_int) = forward;
+            action.sa_sigaction = handler as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            cvt(libc::sigaction(signal, &action, ptr::null_mut()))?;
+        }
+
+        let pid = cvt(libc::fork())?;
+        if pid == 0 {
+            libc::close(read_fd);
+            STATUS_FD.store(write_fd, Ordering::Relaxed);
+            return Ok(());
+        }
+
+        libc::close(write_fd);
+        FORWARD_TO.store(pid, Ordering::Relaxed);
+
+        // Also closes the pipe `Command` uses to detect a successful `exec`,
+        // which would otherwise stay open until the program exits.
+        fd::close_all_except(read_fd);
+
+        let mut status: libc::c_int = 0;
+        let len = mem::size_of::<libc::c_int>();
+        let reported = loop {
+            let ret =
+                libc::read(read_fd, (&mut status as *mut i32).cast(), len);
+            if ret != -1 || errno() != libc::EINTR {
+                break ret == len as isize;
+            }
+        };
+
+        let mut init_status = 0;
+        while libc::waitpid(pid, &mut init_status, 0) == -1 {
+            if errno() != libc::EINTR {
+                break;
+            }
+        }
+
+        exit_like(if reported { status } else { init_status })
+    }
+}
+
+/// Forks the program from the init, returning only within the program.
+///
+/// This is the last step of the setup, so the init is as restricted as the
+/// program and cannot be used to escape it.
+///
+/// Only async-signal-safe operations are used.
+pub(crate) fn fork_program() -> io::Result<()> {
+    let status_fd = STATUS_FD.load(Ordering::Relaxed);
+    unsafe {
+        let pid = cvt(libc::fork())?;
+        if pid == 0 {
+            libc::close(status_fd);
+            return Ok(());
+        }
+
+        FORWARD_TO.store(pid, Ordering::Relaxed);
+        fd::close_all_except(status_fd);
+
+        loop {
+            let mut status = 0;
+            let reaped = libc::waitpid(-1, &mut status, 0);
+            if reaped == pid {
+                libc::write(
+                    status_fd,
+                    (&status as *const i32).cast(),
+                    mem::size_of::<libc::c_int>(),
+                );
+                libc::_exit(0);
+            }
+            if reaped == -1 && errno() != libc::EINTR {
+                libc::_exit(1);
+            }
+        }
+    }
+}
+
+/// Requests `SIGKILL` when the process outside the namespace dies, so that
+/// the namespace does not outlive it.
+///
+/// This must be called after credentials change, which clears the request.
+pub(crate) fn kill_with_parent() -> io::Result<()> {
+    unsafe {
+        cvt(libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0))?;
+    }
+    Ok(())
+}
+
+/// Exits with the same wait status as the program.
+unsafe fn exit_like(status: libc::c_int) -> ! {
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+
+        // The program already dumped core if it was going to.
+        let no_core = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, ptr::null_mut());
+        libc::kill(libc::getpid(), signal);
+
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+fn errno() -> libc::c_int {
+    io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
@@ -0,0 +1,385 @@
+//! Restricting filesystem access with
+//! [Landlock](https://docs.kernel.org/userspace-api/landlock.html).
+//!
+//! A [`Ruleset`] lists the paths a command may access and how. Everything
+//! else is denied, as far as the kernel's Landlock ABI allows. Landlock
+//! requires neither privileges nor namespaces, so a ruleset can also
+//! [`restrict`](Ruleset::restrict) a plain `Command` where
+//! [`Chroot`](crate::Chroot) is unavailable.
+//!
+//! # Examples
+//!
+//! ```
+//! # return;
+//! use beach::landlock::Ruleset;
+//! use std::process::Command;
+//!
+//! let mut command = Command::new("make");
+//! command.current_dir("/home/me/src");
+//!
+//! let enforcement = Ruleset::new()
+//!     .execute("/usr")
+//!     .read_only("/etc")
+//!     .read_write("/home/me/src")
+//!     .read_write("/dev/null")
+//!     .restrict(&mut command)
+//!     .unwrap();
+//!
+//! if !enforcement.is_full() {
+//!     eprintln!("unrestricted: {:?}", enforcement.unrestricted);
+//! }
+//! command.status().unwrap();
+//! ```
+
+use crate::{
+    caps::{self, Capability},
+    mount,
+    setup::cvt,
+    Error,
+};
+use std::{
+    ffi::CString,
+    io, mem,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A filesystem access right that Landlock can restrict.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Access {
+    /// Execute a file.
+    Execute,
+    /// Open a file with write access.
+    WriteFile,
+    /// Open a file with read access.
+    ReadFile,
+    /// List the contents of a directory.
+    ReadDir,
+    /// Remove an empty directory or rename one.
+    RemoveDir,
+    /// Unlink or rename a file.
+    RemoveFile,
+    /// Create a character device.
+    MakeChar,
+    /// Create or rename a directory.
+    MakeDir,
+    /// Create or rename a regular file.
+    MakeReg,
+    /// Create a UNIX domain socket.
+    MakeSock,
+    /// Create a named pipe.
+    MakeFifo,
+    /// Create a block device.
+    MakeBlock,
+    /// Create a symbolic link.
+    MakeSym,
+    /// Link or rename a file to a different directory (ABI 2).
+    Refer,
+    /// Truncate a file (ABI 3).
+    Truncate,
+    /// Use `ioctl(2)` on a device file (ABI 5).
+    IoctlDev,
+}
+
+impl Access {
+    /// Every access right, in order.
+    pub const ALL: &'static [Access] = &[
+        Self::Execute,
+        Self::WriteFile,
+        Self::ReadFile,
+        Self::ReadDir,
+        Self::RemoveDir,
+        Self::RemoveFile,
+        Self::MakeChar,
+        Self::MakeDir,
+        Self::MakeReg,
+        Self::MakeSock,
+        Self::MakeFifo,
+        Self::MakeBlock,
+        Self::MakeSym,
+        Self::Refer,
+        Self::Truncate,
+        Self::IoctlDev,
+    ];
+
+    /// Returns the Landlock ABI version that introduced the access right.
+    pub fn abi(self) -> u32 {
+        match self {
+            Self::Refer => 2,
+            Self::Truncate => 3,
+            Self::IoctlDev => 5,
+            _ => 1,
+        }
+    }
+
+    fn bit(self) -> u64 {
+        1 << self as u64
+    }
+}
+
+/// Rights that read a path.
+const READ: &[Access] = &[Access::ReadFile, Access::ReadDir];
+
+/// Rights that apply to files rather than directories.
+const FILE: &[Access] = &[
+    Access::Execute,
+    Access::WriteFile,
+    Access::ReadFile,
+    Access::Truncate,
+    Access::IoctlDev,
+];
+
+fn mask(access: &[Access]) -> u64 {
+    access.iter().fold(0, |mask, access| mask | access.bit())
+}
+
+/// What a [`Ruleset`] enforces on the running kernel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Enforcement {
+    /// The Landlock ABI version supported by the kernel, or 0 if Landlock is
+    /// unavailable.
+    pub abi: u32,
+    /// The access rights that are denied outside of the rules.
+    pub restricted: Vec<Access>,
+    /// The access rights that the kernel cannot restrict, which remain
+    /// allowed everywhere.
+    pub unrestricted: Vec<Access>,
+}
+
+impl Enforcement {
+    /// Returns whether every access right is restricted.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.unrestricted.is_empty()
+    }
+
+    fn handled(&self) -> u64 {
+        mask(&self.restricted)
+    }
+}
+
+/// Path-based filesystem access rules.
+///
+/// Rules apply to the path and everything beneath it. When a ruleset is used
+/// with [`Chroot::landlock`](crate::Chroot::landlock), paths are resolved
+/// within the root directory, after mounts are made.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Ruleset {
+    rules: Vec<(PathBuf, u64)>,
+    strict: bool,
+}
+
+impl Ruleset {
+    /// Creates a ruleset that denies all filesystem access.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows `access` beneath `path`.
+    ///
+    /// Rights that only apply to directories are ignored when `path` is a
+    /// file.
+    pub fn allow<P: AsRef<Path>>(mut self, path: P, access: &[Access]) -> Self {
+        self.rules.push((path.as_ref().to_owned(), mask(access)));
+        self
+    }
+
+    /// Allows reading files and listing directories beneath `path`.
+    pub fn read_only<P: AsRef<Path>>(self, path: P) -> Self {
+        self.allow(path, READ)
+    }
+
+    /// Allows every kind of access beneath `path`, except executing files.
+    pub fn read_write<P: AsRef<Path>>(self, path: P) -> Self {
+        let access: Vec<Access> = Access::ALL
+            .iter()
+            .copied()
+            .filter(|&access| access != Access::Execute)
+            .collect();
+        self.allow(path, &access)
+    }
+
+    /// Allows reading and executing files beneath `path`.
+    pub fn execute<P: AsRef<Path>>(self, path: P) -> Self {
+        self.allow(path, &[Access::Execute, Access::ReadFile, Access::ReadDir])
+    }
+
+    /// Fails instead of running the command when the kernel cannot restrict
+    /// every access right.
+    #[inline]
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Returns what the ruleset enforces on the running kernel.
+    pub fn enforcement(&self) -> Enforcement {
+        let abi = abi_version();
+        let (restricted, unrestricted) =
+            Access::ALL.iter().partition(|access| abi >= access.abi());
+        Enforcement {
+            abi,
+            restricted,
+            unrestricted,
+        }
+    }
+
+    /// Prepares the ruleset to be applied in the child.
+    pub(crate) fn prepare(&self) -> Result<(Prepared, Enforcement), Error> {
+        let enforcement = self.enforcement();
+        if self.strict && !enforcement.is_full() {
+            return Err(Error::Unsupported(
+                "Landlock access rights on this kernel",
+            ));
+        }
+
+        let handled = enforcement.handled();
+        let mut rules = Vec::with_capacity(self.rules.len());
+        for (path, access) in &self.rules {
+            rules.push((mount::cstring(path)?, access & handled));
+        }
+        Ok((Prepared { handled, rules }, enforcement))
+    }
+
+    /// Restricts `command` with the ruleset, returning what is enforced.
+    ///
+    /// Paths are resolved when the command is spawned. Unless the calling
+    /// process has `CAP_SYS_ADMIN`, `PR_SET_NO_NEW_PRIVS` is set, as the
+    /// kernel requires.
+    pub fn restrict(
+        &self,
+        command: &mut Command,
+    ) -> Result<Enforcement, Error> {
+        let (prepared, enforcement) = self.prepare()?;
+
+        // SAFETY: `Prepared::apply` only performs async-signal-safe
+        // operations.
+        unsafe {
+            command.pre_exec(move || prepared.apply());
+        }
+        Ok(enforcement)
+    }
+}
+
+/// `LANDLOCK_CREATE_RULESET_VERSION`
+const CREATE_RULESET_VERSION: u32 = 1;
+
+/// `LANDLOCK_RULE_PATH_BENEATH`
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+/// The part of `struct landlock_ruleset_attr` supported by every ABI.
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Returns the Landlock ABI version of the kernel, or 0 if unsupported.
+fn abi_version() -> u32 {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0,
+            CREATE_RULESET_VERSION,
+        )
+    };
+    if ret < 0 {
+        0
+    } else {
+        ret as u32
+    }
+}
+
+/// A ruleset prepared in the parent so that it can be applied in the child
+/// without allocating.
+#[derive(Debug)]
+pub(crate) struct Prepared {
+    handled: u64,
+    rules: Vec<(CString, u64)>,
+}
+
+impl Prepared {
+    /// Restricts the calling process.
+    ///
+    /// Only async-signal-safe operations are used.
+    pub fn apply(&self) -> io::Result<()> {
+        if self.handled == 0 {
+            return Ok(());
+        }
+
+        if !caps::has_effective(Capability::SysAdmin)? {
+            unsafe {
+                cvt(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            }
+        }
+
+        let attr = RulesetAttr {
+            handled_access_fs: self.handled,
+        };
+        let ruleset = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr,
+                mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        let ruleset = cvt(ruleset as libc::c_int)?;
+
+        let result = self.add_rules(ruleset).and_then(|()| unsafe {
+            cvt(libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0)
+                as libc::c_int)
+            .map(drop)
+        });
+        unsafe {
+            libc::close(ruleset);
+        }
+        result
+    }
+
+    fn add_rules(&self, ruleset: libc::c_int) -> io::Result<()> {
+        for (path, access) in &self.rules {
+            unsafe {
+                let fd = cvt(libc::open(
+                    path.as_ptr(),
+                    libc::O_PATH | libc::O_CLOEXEC,
+                ))?;
+
+                let mut stat: libc::stat = mem::zeroed();
+                let mut allowed = *access;
+                let result = cvt(libc::fstat(fd, &mut stat)).and_then(|_| {
+                    if stat.st_mode & libc::S_IFMT != libc::S_IFDIR {
+                        allowed &= mask(FILE);
+                    }
+                    if allowed == 0 {
+                        return Ok(0);
+                    }
+                    let attr = PathBeneathAttr {
+                        allowed_access: allowed,
+                        parent_fd: fd,
+                    };
+                    cvt(libc::syscall(
+                        libc::SYS_landlock_add_rule,
+                        ruleset,
+                        RULE_PATH_BENEATH,
+                        &attr,
+                        0,
+                    ) as libc::c_int)
+                });
+                libc::close(fd);
+                result?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -4,7 +4,41 @@
 
 #![deny(missing_docs)]
 
+mod caps;
+mod cgroup;
+mod child;
 mod chroot;
+mod elf;
+mod error;
+mod fd;
+mod ids;
+mod init;
+mod mount;
+mod net;
+mod overlay;
+mod path;
+mod rlimit;
+mod root;
+mod setup;
+
+pub mod landlock;
+#[cfg(feature = "serde")]
+pub mod oci;
+#[cfg(feature = "serde")]
+pub mod profile;
+pub mod seccomp;
 
 #[doc(inline)]
-pub use self::chroot::Chroot;
+pub use self::{
+    caps::{CapSet, Capability, ParseCapabilityError},
+    cgroup::{Cgroup, CgroupStats, IoMax},
+    child::{Child, Exit, Outcome, Usage},
+    chroot::{Backend, Chroot},
+    error::Error,
+    ids::{IdResolver, Ids, ResolveError},
+    net::Network,
+    overlay::{Change, ChangeKind, Overlay},
+    rlimit::Resource,
+    root::{BuildError, RootBuilder},
+    setup::Stage,
+};
@@ -0,0 +1,559 @@
+use crate::{child::Progress, path, setup::cvt, Error};
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs, io, mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+/// `mount_setattr(2)`, which libc does not define for every target. Since
+/// Linux 5.1, new system calls have the same number on every architecture,
+/// relative to its base.
+const SYS_MOUNT_SETATTR: libc::c_long = libc::SYS_close_range + 6;
+
+/// `MOUNT_ATTR_RDONLY` from `linux/mount.h`.
+const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+/// `struct mount_attr` from `linux/mount.h`.
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
+/// Device nodes bind-mounted from the host by
+/// [`minimal_dev`](crate::Chroot::minimal_dev).
+const DEV_NODES: &[&str] =
+    &["null", "zero", "full", "random", "urandom", "tty"];
+
+/// Symbolic links created by [`minimal_dev`](crate::Chroot::minimal_dev).
+const DEV_LINKS: &[(&str, &str)] = &[
+    ("fd", "/proc/self/fd"),
+    ("stdin", "/proc/self/fd/0"),
+    ("stdout", "/proc/self/fd/1"),
+    ("stderr", "/proc/self/fd/2"),
+];
+
+/// A mount configured on [`Chroot`](crate::Chroot), with the target given
+/// relative to the root directory.
+#[derive(Clone, Debug)]
+pub(crate) enum Mount {
+    /// Makes `source` on the host visible at `target`.
+    Bind {
+        source: PathBuf,
+        target: PathBuf,
+        read_only: bool,
+    },
+    /// Mounts a new `proc` filesystem at `/proc`.
+    Proc,
+    /// Mounts a `tmpfs` at `/dev` populated with safe device nodes.
+    Dev,
+    /// Mounts a `tmpfs` at `target`, limited to `size` bytes if nonzero.
+    Tmpfs { target: PathBuf, size: u64 },
+}
+
+/// Decides where missing mount points may be created, which is only within
+/// filesystems mounted for the command, so that they do not outlive it.
+#[derive(Debug)]
+pub(crate) struct MountPoints<'a> {
+    root: &'a Path,
+    /// The directories that make up the root, in one of which a mount point
+    /// must otherwise exist.
+    layers: Vec<&'a Path>,
+    /// Filesystems mounted by earlier steps, which start out empty.
+    scratch: Vec<PathBuf>,
+}
+
+impl<'a> MountPoints<'a> {
+    pub fn new(root: &'a Path, layers: Vec<&'a Path>) -> Self {
+        Self {
+            root,
+            layers,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Allows creating mount points below `dir`, a host path within the
+    /// root.
+    pub fn scratch(&mut self, dir: PathBuf) {
+        self.scratch.push(dir);
+    }
+}
+
+impl Mount {
+    /// Resolves paths within the root and prepares the steps to perform in
+    /// the child.
+    pub fn prepare(
+        &self,
+        points: &mut MountPoints,
+    ) -> Result<Vec<Step>, Error> {
+        let root = points.root;
+        match self {
+            Self::Bind {
+                source,
+                target,
+                read_only,
+            } => {
+                let op = MountOp::bind(points, source, target, *read_only)?;
+                Ok(vec![Step::Mount(op)])
+            }
+            Self::Proc => {
+                let proc = Path::new("/proc");
+                let mut op = MountOp::new(points, proc, false)?;
+                op.source = Some(CString::new("proc").unwrap());
+                op.fstype = op.source.clone();
+                op.flags = libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC;
+
+                // A new `proc` cannot be mounted in a user namespace without
+                // a PID namespace, in which case the host's is used.
+                let fallback = MountOp::bind(points, proc, proc, false)?;
+                op.fallback = Some(Box::new(fallback));
+
+                Ok(vec![Step::Mount(op)])
+            }
+            Self::Dev => {
+                let mut op = MountOp::new(points, Path::new("/dev"), false)?;
+                op.source = Some(CString::new("tmpfs").unwrap());
+                op.fstype = op.source.clone();
+                op.flags = libc::MS_NOSUID | libc::MS_STRICTATIME;
+                op.data = Some(CString::new("mode=755").unwrap());
+
+                let dev = path::resolve_in_root(root, Path::new("/dev"))?;
+                let mut steps = vec![Step::Mount(op)];
+
+                // The new `tmpfs` is empty, so every mount point is created.
+                for node in DEV_NODES {
+                    let source = Path::new("/dev").join(node);
+                    let mut op = MountOp::at(cstring(&dev.join(node))?);
+                    op.source = Some(cstring(&source)?);
+                    op.flags = libc::MS_BIND;
+                    op.create_file = true;
+                    steps.push(Step::Mount(op));
+                }
+                for (link, target) in DEV_LINKS {
+                    steps.push(Step::Symlink {
+                        target: CString::new(*target).unwrap(),
+                        link: cstring(&dev.join(link))?,
+                    });
+                }
+                for dir in &["pts", "shm"] {
+                    steps.push(Step::Mkdir(cstring(&dev.join(dir))?));
+                }
+
+                points.scratch(dev);
+                Ok(steps)
+            }
+            Self::Tmpfs { target, size } => {
+                let mut op = MountOp::new(points, target, false)?;
+                points.scratch(path::resolve_in_root(root, target)?);
+                op.source = Some(CString::new("tmpfs").unwrap());
+                op.fstype = op.source.clone();
+                op.flags = libc::MS_NOSUID | libc::MS_NODEV;
+
+                let mut data = String::from("mode=1777");
+                if *size != 0 {
+                    data.push_str(&format!(",size={}", size));
+                }
+                op.data = Some(CString::new(data).unwrap());
+
+                Ok(vec![Step::Mount(op)])
+            }
+        }
+    }
+}
+
+/// An operation performed in the child to set up the mount namespace.
+#[derive(Debug)]
+pub(crate) enum Step {
+    Mount(MountOp),
+    Symlink { target: CString, link: CString },
+    Mkdir(CString),
+}
+
+impl Step {
+    /// Performs the step, recording the paths involved in `progress`. Only
+    /// async-signal-safe operations are used.
+    pub fn run(&self, progress: &mut Progress) -> io::Result<()> {
+        match self {
+            Self::Mount(op) => op.run(progress),
+            Self::Symlink { target, link } => unsafe {
+                progress.path(link.as_bytes_with_nul());
+                cvt(libc::symlink(target.as_ptr(), link.as_ptr())).map(drop)
+            },
+            Self::Mkdir(dir) => {
+                progress.path(dir.as_bytes_with_nul());
+                mkdir(dir)
+            }
+        }
+    }
+}
+
+/// A mount prepared in the parent so that it can be performed in the child
+/// without allocating.
+#[derive(Debug)]
+pub(crate) struct MountOp {
+    source: Option<CString>,
+    target: CString,
+    fstype: Option<CString>,
+    flags: libc::c_ulong,
+    data: Option<CString>,
+    read_only: bool,
+    /// Mounts beneath the target, made read-only one at a time if
+    /// `mount_setattr(2)` is unavailable.
+    submounts: Vec<CString>,
+    /// Missing directories leading up to the mount point, including it if it
+    /// is a directory.
+    create_dirs: Vec<CString>,
+    /// Whether the mount point is a file that must be created.
+    create_file: bool,
+    /// Performed instead if the mount is not permitted.
+    fallback: Option<Box<MountOp>>,
+}
+
+impl MountOp {
+    fn at(target: CString) -> Self {
+        Self {
+            source: None,
+            target,
+            fstype: None,
+            flags: 0,
+            data: None,
+            read_only: false,
+            submounts: Vec::new(),
+            create_dirs: Vec::new(),
+            create_file: false,
+            fallback: None,
+        }
+    }
+
+    /// Mounts a `tmpfs` with the options in `data` directly over `dir`.
+    pub fn tmpfs(dir: &Path, data: &str) -> Result<Self, Error> {
+        let mut op = Self::at(cstring(dir)?);
+        op.source = Some(CString::new("tmpfs").unwrap());
+        op.fstype = op.source.clone();
+        op.flags = libc::MS_NOSUID | libc::MS_NODEV;
+        op.data = Some(CString::new(data).unwrap());
+        Ok(op)
+    }
+
+    /// Mounts an overlayfs with the options in `data` directly over `dir`.
+    pub fn overlay(dir: &Path, data: CString) -> Result<Self, Error> {
+        let mut op = Self::at(cstring(dir)?);
+        op.source = Some(CString::new("overlay").unwrap());
+        op.fstype = op.source.clone();
+        op.data = Some(data);
+        Ok(op)
+    }
+
+    /// Prepares a mount at `target`, which must exist unless it is within a
+    /// filesystem mounted by an earlier step.
+    fn new(
+        points: &MountPoints,
+        target: &Path,
+        is_file: bool,
+    ) -> Result<Self, Error> {
+        let resolved = path::resolve_in_root(points.root, target)?;
+        let mut op = Self::at(cstring(&resolved)?);
+
+        let scratch = points
+            .scratch
+            .iter()
+            .rev()
+            .find(|dir| resolved.starts_with(dir) && resolved != **dir);
+        if let Some(scratch) = scratch {
+            // The filesystem is empty, whatever the host has beneath it.
+            op.create_file = is_file;
+            let missing = resolved
+                .ancestors()
+                .skip(is_file as usize)
+                .take_while(|dir| dir != scratch);
+            for dir in missing {
+                op.create_dirs.push(cstring(dir)?);
+            }
+            op.create_dirs.reverse();
+            return Ok(op);
+        }
+
+        let exists = points.layers.iter().any(|layer| {
+            path::resolve_in_root(layer, target)
+                .is_ok_and(|path| fs::symlink_metadata(path).is_ok())
+        });
+        if !exists {
+            return Err(Error::MountPointNotFound(target.to_owned()));
+        }
+        Ok(op)
+    }
+
+    fn bind(
+        points: &MountPoints,
+        source: &Path,
+        target: &Path,
+        read_only: bool,
+    ) -> Result<Self, Error> {
+        let meta = match fs::metadata(source) {
+            Ok(meta) => meta,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::MountSourceNotFound(source.to_owned()));
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut op = Self::new(points, target, !meta.is_dir())?;
+        op.source = Some(cstring(source)?);
+        op.flags = libc::MS_BIND | libc::MS_REC;
+        op.read_only = read_only;
+        if read_only {
+            let target = Path::new(OsStr::from_bytes(op.target.as_bytes()));
+            for submount in submounts(source)? {
+                op.submounts.push(cstring(&target.join(submount))?);
+            }
+        }
+        Ok(op)
+    }
+
+    fn run(&self, progress: &mut Progress) -> io::Result<()> {
+        for dir in &self.create_dirs {
+            progress.path(dir.as_bytes_with_nul());
+            mkdir(dir)?;
+        }
+
+        progress.path(self.target.as_bytes_with_nul());
+        unsafe {
+            if self.create_file {
+                let fd = cvt(libc::open(
+                    self.target.as_ptr(),
+                    libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                    0o644,
+                ))?;
+                libc::close(fd);
+            }
+
+            let ret = libc::mount(
+                opt_ptr(&self.source),
+                self.target.as_ptr(),
+                opt_ptr(&self.fstype),
+                self.flags,
+                opt_ptr(&self.data).cast(),
+            );
+            if ret == -1 {
+                let error = io::Error::last_os_error();
+                return match &self.fallback {
+                    Some(fallback)
+                        if error.raw_os_error() == Some(libc::EPERM) =>
+                    {
+                        fallback.run(progress)
+                    }
+                    _ => {
+                        // The source of a bind mount may have been removed
+                        // since the command was created.
+                        if let Some(source) = &self.source {
+                            if error.raw_os_error() == Some(libc::ENOENT)
+                                && self.flags & libc::MS_BIND != 0
+                                && libc::access(source.as_ptr(), libc::F_OK)
+                                    == -1
+                            {
+                                progress
+                                    .missing_source(source.as_bytes_with_nul());
+                            }
+                        }
+                        Err(error)
+                    }
+                };
+            }
+
+            if self.read_only {
+                make_read_only(&self.target, &self.submounts, progress)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Makes every mount in the current mount namespace private so that changes
+/// do not propagate back to the host.
+pub(crate) fn make_private() -> io::Result<()> {
+    unsafe {
+        cvt(libc::mount(
+            ptr::null(),
+            b"/\0".as_ptr().cast(),
+            ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            ptr::null(),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Bind-mounts `dir` onto itself, making it a mount point.
+pub(crate) fn bind_self(dir: &CString) -> io::Result<()> {
+    unsafe {
+        cvt(libc::mount(
+            dir.as_ptr(),
+            dir.as_ptr(),
+            ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            ptr::null(),
+        ))?;
+    }
+    Ok(())
+}
+
+/// Makes the bind mount at `target` and every mount beneath it read-only.
+///
+/// `mount_setattr(2)` requires Linux 5.12. Before that, the `submounts` found
+/// when the command was created are remounted one by one.
+unsafe fn make_read_only(
+    target: &CString,
+    submounts: &[CString],
+    progress: &mut Progress,
+) -> io::Result<()> {
+    let attr = MountAttr {
+        attr_set: MOUNT_ATTR_RDONLY,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: 0,
+    };
+    let ret = libc::syscall(
+        SYS_MOUNT_SETATTR,
+        libc::AT_FDCWD,
+        target.as_ptr(),
+        libc::AT_RECURSIVE as libc::c_uint,
+        &attr,
+        mem::size_of::<MountAttr>(),
+    );
+    if ret == 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    if error.raw_os_error() != Some(libc::ENOSYS) {
+        return Err(error);
+    }
+
+    remount_read_only(target)?;
+    for submount in submounts {
+        progress.path(submount.as_bytes_with_nul());
+        remount_read_only(submount)?;
+    }
+    Ok(())
+}
+
+/// Remounts the bind mount at `target` as read-only.
+///
+/// Flags that are locked in a user namespace must be preserved, so they are
+/// read from the existing mount.
+unsafe fn remount_read_only(target: &CStr) -> io::Result<()> {
+    let mut stat: libc::statvfs = mem::zeroed();
+    cvt(libc::statvfs(target.as_ptr(), &mut stat))?;
+
+    let mut flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+    for &(st, ms) in &[
+        (libc::ST_NOSUID, libc::MS_NOSUID),
+        (libc::ST_NODEV, libc::MS_NODEV),
+        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+        (libc::ST_NOATIME, libc::MS_NOATIME),
+        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+        (libc::ST_RELATIME, libc::MS_RELATIME),
+    ] {
+        if stat.f_flag & st != 0 {
+            flags |= ms;
+        }
+    }
+
+    cvt(libc::mount(
+        ptr::null(),
+        target.as_ptr(),
+        ptr::null(),
+        flags,
+        ptr::null(),
+    ))?;
+    Ok(())
+}
+
+/// Returns the mount points beneath `dir` relative to it, parents first.
+fn submounts(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = fs::canonicalize(dir)?;
+    let mountinfo = fs::read("/proc/self/mountinfo")?;
+    Ok(mount_points(&mountinfo)
+        .filter_map(|point| point.strip_prefix(&dir).ok().map(Path::to_owned))
+        .filter(|relative| !relative.as_os_str().is_empty())
+        .collect())
+}
+
+/// Parses the mount points listed in `/proc/self/mountinfo`, in order.
+fn mount_points(mountinfo: &[u8]) -> impl Iterator<Item = PathBuf> + '_ {
+    mountinfo.split(|&b| b == b'\n').filter_map(|line| {
+        let field = line.split(|&b| b == b' ').nth(4)?;
+        Some(PathBuf::from(OsStr::from_bytes(&unescape(field))))
+    })
+}
+
+/// Decodes the octal escapes the kernel uses for whitespace and backslashes
+/// in `/proc/self/mountinfo`.
+fn unescape(field: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(field.len());
+    let mut i = 0;
+    while i < field.len() {
+        let octal = field.get(i + 1..i + 4).filter(|digits| {
+            digits[0] <= b'3'
+                && digits.iter().all(|d| (b'0'..=b'7').contains(d))
+        });
+        match octal {
+            Some(digits) if field[i] == b'\\' => {
+                out.push(digits.iter().fold(0, |n, d| n * 8 + (d - b'0')));
+                i += 4;
+            }
+            _ => {
+                out.push(field[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Creates `dir`, succeeding if it already exists.
+fn mkdir(dir: &CString) -> io::Result<()> {
+    if unsafe { libc::mkdir(dir.as_ptr(), 0o755) } == -1 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EEXIST) {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+fn opt_ptr(s: &Option<CString>) -> *const libc::c_char {
+    s.as_ref().map_or(ptr::null(), |s| s.as_ptr())
+}
+
+pub(crate) fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_points_are_unescaped() {
+        let mountinfo = b"\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 / /src/a\\040b rw - tmpfs tmpfs rw
+24 23 0:22 / /src/a\\040b/back\\134slash\\012 rw - tmpfs tmpfs rw
+";
+        let points: Vec<_> = mount_points(mountinfo).collect();
+        assert_eq!(
+            points,
+            [
+                Path::new("/"),
+                Path::new("/src/a b"),
+                Path::new("/src/a b/back\\slash\n"),
+            ]
+        );
+    }
+}
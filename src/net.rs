@@ -0,0 +1,124 @@
+use crate::setup::cvt;
+use std::{io, mem};
+
+/// The network access of a command, set with
+/// [`Chroot::network`](crate::Chroot::network).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Network {
+    /// A new network namespace without any interfaces that are up, so no
+    /// network is reachable, not even `127.0.0.1`.
+    None,
+    /// A new network namespace where only the loopback interface `lo` is up,
+    /// so the command can use `127.0.0.1` but nothing else.
+    Loopback,
+    /// The host's network namespace.
+    Host,
+}
+
+impl Default for Network {
+    #[inline]
+    fn default() -> Self {
+        Network::Host
+    }
+}
+
+/// The index of `lo`, which is the first interface of every new network
+/// namespace.
+const LOOPBACK_INDEX: i32 = 1;
+
+#[repr(C)]
+struct IfInfoMsg {
+    family: u8,
+    pad: u8,
+    kind: u16,
+    index: i32,
+    flags: u32,
+    change: u32,
+}
+
+#[repr(C)]
+struct Request {
+    header: libc::nlmsghdr,
+    info: IfInfoMsg,
+}
+
+/// The acknowledgement of a request, a `struct nlmsgerr`.
+#[repr(C)]
+struct Ack {
+    header: libc::nlmsghdr,
+    error: i32,
+    request: libc::nlmsghdr,
+}
+
+/// Brings up `lo` in the current network namespace with an `RTM_NEWLINK`
+/// request over rtnetlink.
+///
+/// Only async-signal-safe operations are used.
+pub(crate) fn loopback_up() -> io::Result<()> {
+    let request = Request {
+        header: libc::nlmsghdr {
+            nlmsg_len: mem::size_of::<Request>() as u32,
+            nlmsg_type: libc::RTM_NEWLINK,
+            nlmsg_flags: (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16,
+            nlmsg_seq: 1,
+            nlmsg_pid: 0,
+        },
+        info: IfInfoMsg {
+            family: libc::AF_UNSPEC as u8,
+            pad: 0,
+            kind: 0,
+            index: LOOPBACK_INDEX,
+            flags: libc::IFF_UP as u32,
+            change: libc::IFF_UP as u32,
+        },
+    };
+
+    unsafe {
+        let fd = cvt(libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        ))?;
+        let result = exchange(fd, &request);
+        libc::close(fd);
+        result
+    }
+}
+
+unsafe fn exchange(fd: libc::c_int, request: &Request) -> io::Result<()> {
+    let mut address: libc::sockaddr_nl = mem::zeroed();
+    address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+
+    let sent = libc::sendto(
+        fd,
+        (request as *const Request).cast(),
+        mem::size_of::<Request>(),
+        0,
+        (&address as *const libc::sockaddr_nl).cast(),
+        mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+    );
+    if sent == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut ack: Ack = mem::zeroed();
+    let received =
+        libc::recv(fd, (&mut ack as *mut Ack).cast(), mem::size_of::<Ack>(), 0);
+    if received == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if (received as usize) < mem::size_of::<Ack>()
+        || ack.header.nlmsg_type != libc::NLMSG_ERROR as u16
+    {
+        return Err(io::Error::from_raw_os_error(libc::EPROTO));
+    }
+    if ack.error != 0 {
+        return Err(io::Error::from_raw_os_error(-ack.error));
+    }
+    Ok(())
+}
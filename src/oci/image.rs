@@ -0,0 +1,715 @@
+//! Unpacking images in the
+//! [OCI image layout](https://github.com/opencontainers/image-spec/blob/main/image-layout.md)
+//! into root directories.
+//!
+//! An [`ImageLayout`] is a local directory containing `oci-layout`,
+//! `index.json` and content-addressed blobs, such as one written by
+//! `skopeo copy docker://alpine oci:alpine:latest`. Its layers are verified
+//! and applied in order, so that the resulting directory can be passed as
+//! the `root` of [`Chroot::command`](crate::Chroot::command). Nothing is
+//! fetched over the network.
+//!
+//! This module requires the `image` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # return;
+//! use beach::{oci::image::ImageLayout, Chroot};
+//!
+//! ImageLayout::open("/var/cache/ocean/alpine")
+//!     .unwrap()
+//!     .unpack(Some("latest"), "/build/root")
+//!     .unwrap();
+//!
+//! Chroot::new().command("/build/root", "sh").status().unwrap();
+//! ```
+
+use crate::path;
+use flate2::bufread::MultiGzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::{
+    collections::{BTreeMap, HashSet},
+    error,
+    ffi::OsStr,
+    fmt,
+    fs::{self, File, Permissions},
+    io::{self, BufReader, Read},
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Component, Path, PathBuf},
+};
+
+/// The annotation holding the tag of a manifest in `index.json`.
+const REF_NAME: &str = "org.opencontainers.image.ref.name";
+
+/// The prefix of a file that hides the same name in lower layers.
+const WHITEOUT: &[u8] = b".wh.";
+
+/// A file that hides the whole contents of its directory in lower layers.
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutFile {
+    image_layout_version: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    media_type: String,
+    digest: String,
+    size: u64,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Platform {
+    architecture: String,
+    os: String,
+}
+
+#[derive(Deserialize)]
+struct Index {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    layers: Vec<Descriptor>,
+}
+
+/// How a layer is compressed.
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// An OCI image layout directory.
+#[derive(Debug)]
+pub struct ImageLayout {
+    dir: PathBuf,
+    tags: Vec<String>,
+}
+
+impl ImageLayout {
+    /// Opens the image layout in `dir`.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, ImageError> {
+        let dir = dir.as_ref().to_owned();
+        let layout: LayoutFile = read_json(&dir.join("oci-layout"))?;
+        if !layout.image_layout_version.starts_with("1.") {
+            return Err(ImageError::Unsupported(format!(
+                "image layout version {}",
+                layout.image_layout_version
+            )));
+        }
+
+        let index: Index = read_json(&dir.join("index.json"))?;
+        let tags = index
+            .manifests
+            .iter()
+            .filter_map(|manifest| manifest.annotations.get(REF_NAME))
+            .cloned()
+            .collect();
+        Ok(Self { dir, tags })
+    }
+
+    /// Returns the tags of the images in the layout.
+    #[inline]
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Applies the layers of the image tagged `tag` to `root`, creating it if
+    /// needed.
+    ///
+    /// `tag` may be omitted if the layout contains a single image. For
+    /// multi-platform images, the manifest for the current architecture is
+    /// used. Every blob is checked against its digest before it is read.
+    ///
+    /// Ownership and extended attributes are only preserved when running as
+    /// root. Device files cannot be created otherwise, and fail the unpack.
+    pub fn unpack<P: AsRef<Path>>(
+        &self,
+        tag: Option<&str>,
+        root: P,
+    ) -> Result<(), ImageError> {
+        let root = root.as_ref();
+        let index: Index = read_json(&self.dir.join("index.json"))?;
+        let tagged = index.manifests.iter().filter(|manifest| {
+            tag.is_none_or(|tag| {
+                manifest.annotations.get(REF_NAME).map(String::as_str)
+                    == Some(tag)
+            })
+        });
+        let manifest = self.manifest(select(tagged)?)?;
+
+        fs::create_dir_all(root)
+            .map_err(|error| ImageError::Io(root.to_owned(), error))?;
+        let mut unpacker = Unpacker {
+            root,
+            dirs: Vec::new(),
+            privileged: unsafe { libc::geteuid() } == 0,
+        };
+        for layer in &manifest.layers {
+            let compression = compression(&layer.media_type)?;
+            let path = self.verify(layer)?;
+            unpacker.layer(&path, compression).map_err(|error| {
+                ImageError::Layer(layer.digest.clone(), error)
+            })?;
+        }
+        unpacker
+            .finish()
+            .map_err(|error| ImageError::Io(root.to_owned(), error))
+    }
+
+    /// Returns the image manifest that `descriptor` refers to, selecting one
+    /// for the current platform from image indexes.
+    fn manifest(
+        &self,
+        descriptor: &Descriptor,
+    ) -> Result<Manifest, ImageError> {
+        let media_type = descriptor.media_type.as_str();
+        match media_type {
+            "application/vnd.oci.image.index.v1+json"
+            | "application/vnd.docker.distribution.manifest.list.v2+json" => {
+                let index: Index = self.blob_json(descriptor)?;
+                self.manifest(select(index.manifests.iter())?)
+            }
+            "application/vnd.oci.image.manifest.v1+json"
+            | "application/vnd.docker.distribution.manifest.v2+json" => {
+                self.blob_json(descriptor)
+            }
+            _ => Err(ImageError::Unsupported(format!(
+                "media type {}",
+                media_type
+            ))),
+        }
+    }
+
+    fn blob_json<T: DeserializeOwned>(
+        &self,
+        descriptor: &Descriptor,
+    ) -> Result<T, ImageError> {
+        read_json(&self.verify(descriptor)?)
+    }
+
+    /// Returns the path of the blob that `descriptor` refers to, after
+    /// checking its size and digest.
+    fn verify(&self, descriptor: &Descriptor) -> Result<PathBuf, ImageError> {
+        let digest = &descriptor.digest;
+        let (algorithm, hex) = digest.split_once(':').unwrap_or(("", ""));
+        let valid_hex = !hex.is_empty()
+            && hex
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        if !valid_hex {
+            return Err(ImageError::Unsupported(format!("digest {}", digest)));
+        }
+
+        let path = self.dir.join("blobs").join(algorithm).join(hex);
+        let io_error = |error| ImageError::Io(path.clone(), error);
+        let mut file = File::open(&path).map_err(io_error)?;
+        let (size, actual) = match algorithm {
+            "sha256" => hash::<Sha256>(&mut file),
+            "sha512" => hash::<Sha512>(&mut file),
+            _ => {
+                return Err(ImageError::Unsupported(format!(
+                    "digest algorithm {}",
+                    algorithm
+                )))
+            }
+        }
+        .map_err(io_error)?;
+
+        if size != descriptor.size || actual != hex {
+            return Err(ImageError::DigestMismatch(digest.clone()));
+        }
+        Ok(path)
+    }
+}
+
+/// Selects the only descriptor that is for the current platform, if any is.
+fn select<'a, I>(descriptors: I) -> Result<&'a Descriptor, ImageError>
+where
+    I: Iterator<Item = &'a Descriptor>,
+{
+    let mut matching = descriptors.filter(|descriptor| {
+        descriptor.platform.as_ref().is_none_or(|platform| {
+            platform.os == "linux" && Some(&*platform.architecture) == arch()
+        })
+    });
+    match (matching.next(), matching.next()) {
+        (Some(descriptor), None) => Ok(descriptor),
+        (None, _) => Err(ImageError::NoManifest),
+        (Some(_), Some(_)) => Err(ImageError::AmbiguousManifest),
+    }
+}
+
+/// Returns the OCI name of the current architecture.
+fn arch() -> Option<&'static str> {
+    if cfg!(target_arch = "x86_64") {
+        Some("amd64")
+    } else if cfg!(target_arch = "aarch64") {
+        Some("arm64")
+    } else if cfg!(target_arch = "x86") {
+        Some("386")
+    } else if cfg!(target_arch = "arm") {
+        Some("arm")
+    } else if cfg!(target_arch = "riscv64") {
+        Some("riscv64")
+    } else if cfg!(target_arch = "s390x") {
+        Some("s390x")
+    } else if cfg!(all(target_arch = "powerpc64", target_endian = "little")) {
+        Some("ppc64le")
+    } else {
+        None
+    }
+}
+
+fn compression(media_type: &str) -> Result<Compression, ImageError> {
+    if media_type.ends_with("+gzip") || media_type.ends_with(".gzip") {
+        Ok(Compression::Gzip)
+    } else if media_type.ends_with("+zstd") {
+        Ok(Compression::Zstd)
+    } else if media_type.ends_with(".tar") {
+        Ok(Compression::None)
+    } else {
+        Err(ImageError::Unsupported(format!(
+            "media type {}",
+            media_type
+        )))
+    }
+}
+
+/// Returns the size and hexadecimal digest of what `reader` yields.
+fn hash<D: Digest + io::Write>(
+    reader: &mut impl Read,
+) -> io::Result<(u64, String)> {
+    let mut hasher = D::new();
+    let size = io::copy(reader, &mut hasher)?;
+    let digest = hasher.finalize();
+    let hex = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok((size, hex))
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, ImageError> {
+    let json = fs::read(path)
+        .map_err(|error| ImageError::Io(path.to_owned(), error))?;
+    serde_json::from_slice(&json)
+        .map_err(|error| ImageError::Json(path.to_owned(), error))
+}
+
+/// Applies layers to a root directory.
+struct Unpacker<'a> {
+    root: &'a Path,
+    /// Directories and their modes, which are set once every layer is
+    /// applied so that read-only directories can be filled in meanwhile.
+    dirs: Vec<(PathBuf, u32)>,
+    privileged: bool,
+}
+
+impl Unpacker<'_> {
+    fn layer(
+        &mut self,
+        path: &Path,
+        compression: Compression,
+    ) -> io::Result<()> {
+        let file = BufReader::new(File::open(path)?);
+        let reader: Box<dyn Read> = match compression {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+            Compression::Zstd => {
+                Box::new(StreamingDecoder::new(file).map_err(|error| {
+                    io::Error::new(io::ErrorKind::InvalidData, error)
+                })?)
+            }
+        };
+
+        let mut archive = tar::Archive::new(reader);
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_ownerships(self.privileged);
+        archive.set_unpack_xattrs(self.privileged);
+        archive.set_overwrite(true);
+
+        // Paths unpacked from this layer, which opaque whiteouts keep.
+        let mut unpacked = HashSet::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = entry.path()?.into_owned();
+            let (parent, name) = match split(&entry_path)? {
+                Some(split) => split,
+                None => continue,
+            };
+
+            // Symbolic links in earlier layers must not lead outside the
+            // root, which `tar` would allow.
+            let dir = path::resolve_in_root(self.root, &parent)?;
+
+            if name == OPAQUE_WHITEOUT {
+                remove_except(&dir, &unpacked)?;
+                continue;
+            }
+            if let Some(hidden) = name.as_bytes().strip_prefix(WHITEOUT) {
+                // `.wh...` would otherwise remove the parent of `dir`.
+                if matches!(hidden, b"" | b"." | b"..")
+                    || hidden.contains(&b'/')
+                {
+                    return Err(invalid(&entry_path));
+                }
+                remove(&dir.join(OsStr::from_bytes(hidden)))?;
+                continue;
+            }
+
+            fs::create_dir_all(&dir)?;
+            let target = dir.join(name);
+            self.entry(&mut entry, &target)?;
+            unpacked.insert(target);
+        }
+        Ok(())
+    }
+
+    fn entry<R: Read>(
+        &mut self,
+        entry: &mut tar::Entry<R>,
+        target: &Path,
+    ) -> io::Result<()> {
+        let kind = entry.header().entry_type();
+        match fs::symlink_metadata(target) {
+            Ok(meta) if meta.is_dir() && kind.is_dir() => {}
+            Ok(_) => remove(target)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        if kind.is_hard_link() {
+            let link = entry.link_name()?.ok_or_else(|| invalid(target))?;
+            let (parent, name) =
+                split(&link)?.ok_or_else(|| invalid(target))?;
+            let source = path::resolve_in_root(self.root, &parent)?.join(name);
+            return fs::hard_link(source, target);
+        }
+
+        entry.unpack(target)?;
+        if kind.is_dir() {
+            let mode = entry.header().mode()?;
+            fs::set_permissions(target, Permissions::from_mode(mode | 0o700))?;
+            self.dirs.push((target.to_owned(), mode));
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<()> {
+        for (dir, mode) in self.dirs.iter().rev() {
+            match fs::set_permissions(dir, Permissions::from_mode(*mode)) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    return Err(error)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits an entry path into its parent directory and file name, or returns
+/// `None` for the root itself.
+fn split(path: &Path) -> io::Result<Option<(PathBuf, &OsStr)>> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(invalid(path))
+            }
+        }
+    }
+    Ok(names
+        .split_last()
+        .map(|(name, parent)| (parent.iter().collect(), *name)))
+}
+
+fn invalid(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid entry {:?}", path),
+    )
+}
+
+/// Removes `path` without following symbolic links, if it exists.
+fn remove(path: &Path) -> io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(error) => Err(error),
+    };
+    match result {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Removes everything beneath `dir` that is not in `keep`.
+fn remove_except(dir: &Path, keep: &HashSet<PathBuf>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        entries => entries?,
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if !keep.contains(&path) {
+            remove(&path)?;
+        } else if fs::symlink_metadata(&path)?.is_dir() {
+            remove_except(&path, keep)?;
+        }
+    }
+    Ok(())
+}
+
+/// An error returned when an image cannot be unpacked.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ImageError {
+    /// A file of the layout or root directory cannot be accessed.
+    Io(PathBuf, io::Error),
+    /// A JSON file of the layout is malformed.
+    Json(PathBuf, serde_json::Error),
+    /// The layout uses a version, media type or digest algorithm that is not
+    /// supported.
+    Unsupported(String),
+    /// A blob does not match its digest or size.
+    DigestMismatch(String),
+    /// No image matches the tag and current platform.
+    NoManifest,
+    /// Several images match, so a tag must be given.
+    AmbiguousManifest,
+    /// A layer, identified by its digest, cannot be applied.
+    Layer(String, io::Error),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(path, error) => write!(f, "{:?}: {}", path, error),
+            Self::Json(path, error) => {
+                write!(f, "invalid JSON in {:?}: {}", path, error)
+            }
+            Self::Unsupported(what) => write!(f, "unsupported {}", what),
+            Self::DigestMismatch(digest) => {
+                write!(f, "blob {} does not match its digest", digest)
+            }
+            Self::NoManifest => {
+                f.write_str("no image for the tag and current platform")
+            }
+            Self::AmbiguousManifest => {
+                f.write_str("several images match, so a tag is required")
+            }
+            Self::Layer(digest, error) => {
+                write!(f, "cannot apply layer {}: {}", digest, error)
+            }
+        }
+    }
+}
+
+impl error::Error for ImageError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(_, error) | Self::Layer(_, error) => Some(error),
+            Self::Json(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    /// Returns an empty directory unique to the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "beach-image-{}-{}",
+            process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes an uncompressed layer of files with `contents`, or of
+    /// directories for `None`.
+    fn layer(path: &Path, entries: &[(&str, Option<&str>)]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for &(name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_path(name).unwrap();
+            match contents {
+                Some(contents) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_mode(0o644);
+                    header.set_size(contents.len() as u64);
+                    header.set_cksum();
+                    builder.append(&header, contents.as_bytes()).unwrap();
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(0o755);
+                    header.set_size(0);
+                    header.set_cksum();
+                    builder.append(&header, io::empty()).unwrap();
+                }
+            }
+        }
+        builder.finish().unwrap();
+    }
+
+    fn unpack(root: &Path, layers: &[&Path]) -> io::Result<()> {
+        let mut unpacker = Unpacker {
+            root,
+            dirs: Vec::new(),
+            privileged: false,
+        };
+        for layer in layers {
+            unpacker.layer(layer, Compression::None)?;
+        }
+        unpacker.finish()
+    }
+
+    /// Returns the paths under `root`, sorted.
+    fn tree(root: &Path) -> Vec<String> {
+        let mut paths = Vec::new();
+        let mut dirs = vec![root.to_owned()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path.clone());
+                }
+                let relative = path.strip_prefix(root).unwrap();
+                paths.push(relative.to_str().unwrap().to_owned());
+            }
+        }
+        paths.sort();
+        paths
+    }
+
+    /// Unpacks a lower layer and then an upper one whose directory `a` is
+    /// opaque, with the whiteout at `opaque` among the entries.
+    fn unpack_opaque(name: &str, opaque: usize) -> Vec<String> {
+        let dir = temp_dir(name);
+        let root = dir.join("root");
+        let lower = dir.join("lower.tar");
+        layer(
+            &lower,
+            &[
+                ("a/", None),
+                ("a/old", Some("")),
+                ("a/shared", Some("lower")),
+                ("a/sub/", None),
+                ("a/sub/lower", Some("")),
+                ("b/", None),
+                ("b/kept", Some("")),
+            ],
+        );
+        let mut entries = vec![
+            ("a/", None),
+            ("a/new", Some("")),
+            ("a/shared", Some("upper")),
+            ("a/sub/", None),
+            ("a/sub/upper", Some("")),
+        ];
+        entries.insert(opaque, ("a/.wh..wh..opq", Some("")));
+        let upper = dir.join("upper.tar");
+        layer(&upper, &entries);
+
+        unpack(&root, &[&lower, &upper]).unwrap();
+        let shared = fs::read_to_string(root.join("a/shared")).unwrap();
+        assert_eq!(shared, "upper");
+        let tree = tree(&root);
+        fs::remove_dir_all(&dir).unwrap();
+        tree
+    }
+
+    #[test]
+    fn opaque_whiteout_keeps_entries_of_its_layer() {
+        let expected = [
+            "a",
+            "a/new",
+            "a/shared",
+            "a/sub",
+            "a/sub/upper",
+            "b",
+            "b/kept",
+        ];
+        // The whiteout may come anywhere among the directory's entries.
+        for opaque in 0..=5 {
+            let name = format!("opaque-{}", opaque);
+            assert_eq!(unpack_opaque(&name, opaque), expected, "{}", opaque);
+        }
+    }
+
+    #[test]
+    fn whiteouts_hide_lower_layers_only() {
+        let dir = temp_dir("whiteout");
+        let root = dir.join("root");
+        let lower = dir.join("lower.tar");
+        layer(
+            &lower,
+            &[("dir/", None), ("dir/file", Some("")), ("file", Some(""))],
+        );
+        let upper = dir.join("upper.tar");
+        layer(&upper, &[(".wh.dir", Some("")), (".wh.file", Some(""))]);
+        let recreated = dir.join("recreated.tar");
+        layer(&recreated, &[("file", Some("again"))]);
+
+        unpack(&root, &[&lower, &upper]).unwrap();
+        assert!(tree(&root).is_empty());
+        unpack(&root, &[&recreated]).unwrap();
+        assert_eq!(tree(&root), ["file"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn whiteout_of_parent_is_rejected() {
+        let dir = temp_dir("whiteout-parent");
+        let root = dir.join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(dir.join("victim"), "").unwrap();
+
+        let path = dir.join("layer.tar");
+        layer(&path, &[(".wh...", Some(""))]);
+        let error = unpack(&root, &[&path]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(dir.join("victim").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn whiteout_of_own_directory_is_rejected() {
+        let dir = temp_dir("whiteout-self");
+        let root = dir.join("root");
+        let path = dir.join("layer.tar");
+        layer(
+            &path,
+            &[
+                ("a/", None),
+                ("a/file", Some("kept")),
+                ("a/.wh..", Some("")),
+            ],
+        );
+        assert!(unpack(&root, &[&path]).is_err());
+        assert!(root.join("a/file").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
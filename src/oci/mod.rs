@@ -0,0 +1,1111 @@
+//! Running [OCI runtime bundles](https://github.com/opencontainers/runtime-spec).
+//!
+//! A [`Bundle`] reads the `config.json` of a bundle directory and translates
+//! it into a [`Chroot`]. Fields that `Chroot` cannot honor are not silently
+//! dropped but listed by [`Bundle::unsupported`], so that callers can decide
+//! whether running the bundle anyway is acceptable.
+//!
+//! This module requires the `serde` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! # return;
+//! use beach::oci::Bundle;
+//!
+//! let bundle = Bundle::load("/path/to/bundle").unwrap();
+//! for field in bundle.unsupported() {
+//!     eprintln!("warning: unsupported: {}", field);
+//! }
+//! bundle.try_command().unwrap().status().unwrap();
+//! ```
+
+#[cfg(feature = "image")]
+pub mod image;
+
+use crate::{
+    seccomp::{Action, Arch, Cmp, Filter, Rule},
+    CapSet, Capability, Cgroup, Child, Chroot, Error, IoMax, Network, Resource,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+/// Fields that do not affect how the bundle runs.
+const IGNORED: &[&str] =
+    &["ociVersion", "annotations", "linux.seccomp.architectures"];
+
+/// Fields that are not recognized, by name.
+type Other = BTreeMap<String, Value>;
+
+/// Sets one of the limits of an [`IoMax`].
+type SetLimit = fn(IoMax, u64) -> IoMax;
+
+#[derive(Deserialize)]
+struct Spec {
+    root: Root,
+    process: Process,
+    hostname: Option<String>,
+    domainname: Option<String>,
+    #[serde(default)]
+    mounts: Vec<Mount>,
+    linux: Option<Linux>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Root {
+    path: PathBuf,
+    #[serde(default)]
+    readonly: bool,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Process {
+    #[serde(default)]
+    terminal: bool,
+    user: Option<User>,
+    args: Vec<String>,
+    #[serde(default)]
+    env: Vec<String>,
+    cwd: Option<PathBuf>,
+    capabilities: Option<Capabilities>,
+    #[serde(default)]
+    rlimits: Vec<Rlimit>,
+    #[serde(default)]
+    no_new_privileges: bool,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct User {
+    uid: u32,
+    gid: u32,
+    #[serde(default)]
+    additional_gids: Vec<u32>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+struct Capabilities {
+    #[serde(default)]
+    bounding: Vec<String>,
+    #[serde(default)]
+    effective: Vec<String>,
+    #[serde(default)]
+    inheritable: Vec<String>,
+    #[serde(default)]
+    permitted: Vec<String>,
+    #[serde(default)]
+    ambient: Vec<String>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+struct Rlimit {
+    #[serde(rename = "type")]
+    kind: String,
+    soft: u64,
+    hard: u64,
+}
+
+#[derive(Deserialize)]
+struct Mount {
+    destination: PathBuf,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    source: Option<PathBuf>,
+    #[serde(default)]
+    options: Vec<String>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Linux {
+    #[serde(default)]
+    namespaces: Vec<Namespace>,
+    #[serde(default)]
+    uid_mappings: Vec<IdMapping>,
+    #[serde(default)]
+    gid_mappings: Vec<IdMapping>,
+    time_offsets: Option<BTreeMap<String, TimeOffset>>,
+    resources: Option<Resources>,
+    seccomp: Option<Seccomp>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+struct Namespace {
+    #[serde(rename = "type")]
+    kind: String,
+    path: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct IdMapping {
+    #[serde(rename = "containerID")]
+    container_id: u32,
+    #[serde(rename = "hostID")]
+    host_id: u32,
+    size: u32,
+}
+
+#[derive(Deserialize)]
+struct TimeOffset {
+    #[serde(default)]
+    secs: i64,
+    #[serde(default)]
+    nanosecs: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Resources {
+    memory: Option<Limits>,
+    pids: Option<Limits>,
+    cpu: Option<Cpu>,
+    #[serde(rename = "blockIO")]
+    block_io: Option<BlockIo>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+/// The memory or PIDs limits of a cgroup.
+#[derive(Deserialize)]
+struct Limits {
+    limit: Option<i64>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+struct Cpu {
+    quota: Option<i64>,
+    period: Option<u64>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockIo {
+    #[serde(default)]
+    throttle_read_bps_device: Vec<Throttle>,
+    #[serde(default)]
+    throttle_write_bps_device: Vec<Throttle>,
+    #[serde(default, rename = "throttleReadIOPSDevice")]
+    throttle_read_iops_device: Vec<Throttle>,
+    #[serde(default, rename = "throttleWriteIOPSDevice")]
+    throttle_write_iops_device: Vec<Throttle>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+struct Throttle {
+    major: u32,
+    minor: u32,
+    rate: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Seccomp {
+    default_action: String,
+    default_errno_ret: Option<u16>,
+    #[serde(default)]
+    syscalls: Vec<Syscall>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Syscall {
+    names: Vec<String>,
+    action: String,
+    errno_ret: Option<u16>,
+    #[serde(default)]
+    args: Vec<Arg>,
+    #[serde(flatten)]
+    other: Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Arg {
+    index: u8,
+    value: u64,
+    #[serde(default)]
+    value_two: u64,
+    op: String,
+}
+
+/// An OCI runtime bundle translated into a [`Chroot`].
+#[derive(Clone, Debug)]
+pub struct Bundle {
+    root: PathBuf,
+    args: Vec<String>,
+    chroot: Chroot,
+    cgroup: Option<Cgroup>,
+    unsupported: Vec<String>,
+}
+
+impl Bundle {
+    /// Loads the `config.json` of the bundle in `dir`.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, LoadError> {
+        let dir = dir.as_ref();
+        let path = dir.join("config.json");
+        let json = fs::read_to_string(&path)
+            .map_err(|error| LoadError::Io(path, error))?;
+        Self::from_json(dir, &json)
+    }
+
+    /// Parses the contents of a `config.json`, resolving relative paths
+    /// against the bundle directory `dir`.
+    pub fn from_json<P: AsRef<Path>>(
+        dir: P,
+        json: &str,
+    ) -> Result<Self, LoadError> {
+        let spec: Spec = serde_json::from_str(json).map_err(LoadError::Json)?;
+        if spec.process.args.is_empty() {
+            return Err(LoadError::NoArgs);
+        }
+        Ok(Translator::default().translate(dir.as_ref(), spec))
+    }
+
+    /// Returns the root directory of the bundle.
+    #[inline]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the program and its arguments.
+    #[inline]
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Returns the configuration that the bundle translates to, which can be
+    /// used to make further changes.
+    #[inline]
+    pub fn chroot(&self) -> &Chroot {
+        &self.chroot
+    }
+
+    /// Returns the cgroup that limits the bundle's resources, of which
+    /// [`spawn`](Self::spawn) creates a copy for the command.
+    #[inline]
+    pub fn cgroup(&self) -> Option<&Cgroup> {
+        self.cgroup.as_ref()
+    }
+
+    /// Returns the fields of `config.json` that are ignored because they
+    /// cannot be honored, such as `linux.maskedPaths` or
+    /// `mounts[/dev/pts]`.
+    #[inline]
+    pub fn unsupported(&self) -> &[String] {
+        &self.unsupported
+    }
+
+    /// Returns a `Command` that runs the bundle's process.
+    ///
+    /// See [`Chroot::command`].
+    pub fn command(&self) -> Command {
+        let mut command = self.chroot.command(&self.root, &self.args[0]);
+        command.args(&self.args[1..]);
+        command
+    }
+
+    /// Returns a `Command` that runs the bundle's process, or an error if it
+    /// would fail to start.
+    ///
+    /// See [`Chroot::try_command`].
+    pub fn try_command(&self) -> Result<Command, Error> {
+        let mut command = self.chroot.try_command(&self.root, &self.args[0])?;
+        command.args(&self.args[1..]);
+        Ok(command)
+    }
+
+    /// Spawns the bundle's process.
+    ///
+    /// See [`Chroot::spawn`].
+    pub fn spawn(&self) -> Result<Child, Error> {
+        self.chroot.spawn(&mut self.try_command()?)
+    }
+}
+
+/// Translates a [`Spec`] while recording what is unsupported.
+#[derive(Default)]
+struct Translator {
+    unsupported: Vec<String>,
+}
+
+impl Translator {
+    fn report<F: fmt::Display>(&mut self, field: F) {
+        self.unsupported.push(field.to_string());
+    }
+
+    /// Reports every field in `other` that is set to something other than
+    /// an empty or false value.
+    fn report_other(&mut self, parent: &str, other: &Other) {
+        for (name, value) in other {
+            let field = if parent.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", parent, name)
+            };
+            let empty = match value {
+                Value::Null | Value::Bool(false) => true,
+                Value::String(s) => s.is_empty(),
+                Value::Array(a) => a.is_empty(),
+                Value::Object(o) => o.is_empty(),
+                _ => false,
+            };
+            if !empty && !IGNORED.contains(&field.as_str()) {
+                self.report(field);
+            }
+        }
+    }
+
+    fn translate(mut self, dir: &Path, spec: Spec) -> Bundle {
+        self.report_other("", &spec.other);
+        self.report_other("root", &spec.root.other);
+        if spec.root.readonly {
+            self.report("root.readonly");
+        }
+
+        let mut chroot = Chroot::new();
+        chroot = self.process(chroot, &spec.process);
+        if let Some(hostname) = &spec.hostname {
+            chroot = chroot.hostname(hostname);
+        }
+        if let Some(domainname) = &spec.domainname {
+            chroot = chroot.domainname(domainname);
+        }
+        for mount in &spec.mounts {
+            chroot = self.mount(chroot, dir, mount);
+        }
+
+        let mut cgroup = None;
+        if let Some(linux) = &spec.linux {
+            let uid = spec.process.user.as_ref().map_or(0, |user| user.uid);
+            let gid = spec.process.user.as_ref().map_or(0, |user| user.gid);
+            chroot = self.namespaces(chroot, linux, (uid, gid), &spec);
+            if let Some(resources) = &linux.resources {
+                cgroup = self.resources(resources);
+            }
+            if let Some(seccomp) = &linux.seccomp {
+                chroot = chroot.seccomp(self.seccomp(seccomp));
+            }
+            self.report_other("linux", &linux.other);
+        }
+        if let Some(cgroup) = &cgroup {
+            chroot = chroot.cgroup(cgroup.clone());
+        }
+
+        Bundle {
+            root: dir.join(&spec.root.path),
+            args: spec.process.args,
+            chroot,
+            cgroup,
+            unsupported: self.unsupported,
+        }
+    }
+
+    fn process(&mut self, mut chroot: Chroot, process: &Process) -> Chroot {
+        self.report_other("process", &process.other);
+        if process.terminal {
+            self.report("process.terminal");
+        }
+
+        if let Some(user) = &process.user {
+            self.report_other("process.user", &user.other);
+            chroot = chroot
+                .user_group(user.uid.to_string(), user.gid.to_string())
+                .groups(user.additional_gids.iter().map(u32::to_string));
+        }
+
+        for var in &process.env {
+            match var.split_once('=') {
+                Some((key, value)) => chroot = chroot.env(key, value),
+                None => self.report(format!("process.env[{}]", var)),
+            }
+        }
+        if let Some(cwd) = &process.cwd {
+            chroot = chroot.current_dir(cwd);
+        }
+
+        // Processes without capabilities get none, rather than those of the
+        // calling process.
+        match &process.capabilities {
+            Some(caps) => {
+                self.report_other("process.capabilities", &caps.other);
+                chroot = chroot
+                    .bounding_caps(self.caps("bounding", &caps.bounding))
+                    .permitted_caps(self.caps("permitted", &caps.permitted))
+                    .effective_caps(self.caps("effective", &caps.effective))
+                    .inheritable_caps(
+                        self.caps("inheritable", &caps.inheritable),
+                    )
+                    .ambient_caps(self.caps("ambient", &caps.ambient));
+            }
+            None => chroot = chroot.capabilities(CapSet::empty()),
+        }
+
+        for limit in &process.rlimits {
+            let resource =
+                Resource::ALL.iter().find(|r| r.name() == limit.kind);
+            match resource {
+                Some(&resource) => {
+                    chroot = chroot.rlimit(resource, limit.soft, limit.hard);
+                }
+                None => self.report(format!("process.rlimits[{}]", limit.kind)),
+            }
+        }
+        if process.no_new_privileges {
+            chroot = chroot.no_new_privs();
+        }
+        chroot
+    }
+
+    fn caps(&mut self, set: &str, names: &[String]) -> CapSet {
+        let mut caps = CapSet::empty();
+        for name in names {
+            match name.parse::<Capability>() {
+                Ok(cap) => caps.insert(cap),
+                Err(_) => self
+                    .report(format!("process.capabilities.{}[{}]", set, name)),
+            }
+        }
+        caps
+    }
+
+    fn mount(&mut self, chroot: Chroot, dir: &Path, mount: &Mount) -> Chroot {
+        let field = format!("mounts[{}]", mount.destination.display());
+        self.report_other(&field, &mount.other);
+
+        let target = &mount.destination;
+        let kind = mount.kind.as_deref().unwrap_or_default();
+        let has = |option: &str| mount.options.iter().any(|o| o == option);
+
+        // Options that the mount made by `Chroot` already has.
+        let (chroot, implied): (_, &[&str]) =
+            if kind == "bind" || has("bind") || has("rbind") {
+                let source = match &mount.source {
+                    Some(source) => dir.join(source),
+                    None => {
+                        self.report(format!("{}.source", field));
+                        return chroot;
+                    }
+                };
+                let chroot = if has("ro") {
+                    chroot.bind_ro(source, target)
+                } else {
+                    chroot.bind(source, target)
+                };
+                (
+                    chroot,
+                    &["bind", "rbind", "ro", "rw", "private", "rprivate"],
+                )
+            } else if kind == "proc" && target == Path::new("/proc") {
+                (chroot.mount_proc(), &["nosuid", "nodev", "noexec"])
+            } else if kind == "sysfs" && target == Path::new("/sys") {
+                (chroot.mount_sys(), &["ro", "nosuid", "nodev", "noexec"])
+            } else if kind == "tmpfs" && target == Path::new("/dev") {
+                (
+                    chroot.minimal_dev(),
+                    &["nosuid", "strictatime", "mode=755", "size"],
+                )
+            } else if kind == "tmpfs" {
+                let size = mount
+                    .options
+                    .iter()
+                    .find_map(|o| o.strip_prefix("size="))
+                    .map_or(Some(0), parse_size);
+                match size {
+                    Some(size) => (
+                        chroot.tmpfs(target, size),
+                        &["nosuid", "nodev", "mode=1777", "size"],
+                    ),
+                    None => {
+                        self.report(format!("{}.options[size]", field));
+                        return chroot;
+                    }
+                }
+            } else {
+                self.report(field);
+                return chroot;
+            };
+
+        for option in &mount.options {
+            let name = option.split('=').next().unwrap_or_default();
+            if !implied.contains(&option.as_str()) && !implied.contains(&name) {
+                self.report(format!("{}.options[{}]", field, option));
+            }
+        }
+        chroot
+    }
+
+    fn namespaces(
+        &mut self,
+        mut chroot: Chroot,
+        linux: &Linux,
+        (uid, gid): (u32, u32),
+        spec: &Spec,
+    ) -> Chroot {
+        let mut user_namespace = false;
+        for namespace in &linux.namespaces {
+            let field = format!("linux.namespaces[{}]", namespace.kind);
+            if namespace.path.is_some() {
+                self.report(format!("{}.path", field));
+                continue;
+            }
+            match namespace.kind.as_str() {
+                "pid" => chroot = chroot.pid_namespace(),
+                "network" => chroot = chroot.network(Network::None),
+                "ipc" => chroot = chroot.ipc_namespace(),
+                // Mounts are always made in a new mount namespace.
+                "mount" => {}
+                "uts" if spec.hostname.is_some() => {}
+                "uts" if spec.domainname.is_some() => {}
+                "user" => {
+                    user_namespace = true;
+                    chroot = chroot.unprivileged();
+                }
+                "time" => {
+                    let offsets = linux.time_offsets.as_ref();
+                    let offset = |clock| {
+                        let offset = offsets.and_then(|o| o.get(clock));
+                        offset.map_or(Some(Duration::ZERO), |offset| {
+                            let secs = u64::try_from(offset.secs).ok()?;
+                            Some(Duration::new(secs, offset.nanosecs))
+                        })
+                    };
+                    match (offset("monotonic"), offset("boottime")) {
+                        (Some(monotonic), Some(boottime)) => {
+                            chroot = chroot.time_namespace(monotonic, boottime)
+                        }
+                        _ => self.report("linux.timeOffsets"),
+                    }
+                }
+                _ => self.report(field),
+            }
+        }
+
+        // Only the calling user can be mapped without privileges.
+        let single = |maps: &[IdMapping], inner: u32, outer: u32| {
+            matches!(maps, [map] if map.size == 1
+                && map.container_id == inner
+                && map.host_id == outer)
+        };
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let uid_mapped =
+            user_namespace && single(&linux.uid_mappings, uid, euid);
+        if !linux.uid_mappings.is_empty() && !uid_mapped {
+            self.report("linux.uidMappings");
+        }
+        let gid_mapped =
+            user_namespace && single(&linux.gid_mappings, gid, egid);
+        if !linux.gid_mappings.is_empty() && !gid_mapped {
+            self.report("linux.gidMappings");
+        }
+        chroot
+    }
+
+    fn resources(&mut self, resources: &Resources) -> Option<Cgroup> {
+        self.report_other("linux.resources", &resources.other);
+
+        let mut cgroup = Cgroup::new();
+        let mut limited = false;
+        let positive = |limit: Option<i64>| match limit {
+            Some(limit) if limit > 0 => Some(limit as u64),
+            _ => None,
+        };
+
+        if let Some(memory) = &resources.memory {
+            self.report_other("linux.resources.memory", &memory.other);
+            if let Some(limit) = positive(memory.limit) {
+                cgroup = cgroup.memory_max(limit);
+                limited = true;
+            }
+        }
+        if let Some(pids) = &resources.pids {
+            self.report_other("linux.resources.pids", &pids.other);
+            if let Some(limit) = positive(pids.limit) {
+                cgroup = cgroup.pids_max(limit);
+                limited = true;
+            }
+        }
+        if let Some(cpu) = &resources.cpu {
+            self.report_other("linux.resources.cpu", &cpu.other);
+            if let Some(quota) = positive(cpu.quota) {
+                let period = cpu.period.unwrap_or(100_000);
+                cgroup = cgroup.cpu_max(
+                    Duration::from_micros(quota),
+                    Duration::from_micros(period),
+                );
+                limited = true;
+            }
+        }
+
+        if let Some(block_io) = &resources.block_io {
+            self.report_other("linux.resources.blockIO", &block_io.other);
+            let mut devices = BTreeMap::new();
+            let throttles: [(_, SetLimit); 4] = [
+                (&block_io.throttle_read_bps_device, IoMax::read_bps),
+                (&block_io.throttle_write_bps_device, IoMax::write_bps),
+                (&block_io.throttle_read_iops_device, IoMax::read_iops),
+                (&block_io.throttle_write_iops_device, IoMax::write_iops),
+            ];
+            for (throttles, set) in throttles {
+                for throttle in throttles {
+                    let device = (throttle.major, throttle.minor);
+                    let limits = devices
+                        .entry(device)
+                        .or_insert_with(|| IoMax::new(device.0, device.1));
+                    *limits = set(*limits, throttle.rate);
+                }
+            }
+            for limits in devices.into_values() {
+                cgroup = cgroup.io_max(limits);
+                limited = true;
+            }
+        }
+
+        if limited {
+            Some(cgroup)
+        } else {
+            None
+        }
+    }
+
+    fn seccomp(&mut self, seccomp: &Seccomp) -> Filter {
+        self.report_other("linux.seccomp", &seccomp.other);
+
+        let field = "linux.seccomp.defaultAction";
+        let default = seccomp.default_errno_ret;
+        let default = self.action(field, &seccomp.default_action, default);
+        let mut filter = Filter::new(default.unwrap_or(Action::KillProcess));
+
+        for syscall in &seccomp.syscalls {
+            self.report_other("linux.seccomp.syscalls", &syscall.other);
+            let field = format!("linux.seccomp.syscalls[{}]", syscall.action);
+            let action =
+                match self.action(&field, &syscall.action, syscall.errno_ret) {
+                    Some(action) => action,
+                    None => continue,
+                };
+
+            let mut args = Vec::new();
+            for arg in &syscall.args {
+                let cmp = match arg.op.as_str() {
+                    "SCMP_CMP_EQ" => Cmp::Eq(arg.value),
+                    "SCMP_CMP_NE" => Cmp::Ne(arg.value),
+                    "SCMP_CMP_LT" => Cmp::Lt(arg.value),
+                    "SCMP_CMP_LE" => Cmp::Le(arg.value),
+                    "SCMP_CMP_GT" => Cmp::Gt(arg.value),
+                    "SCMP_CMP_GE" => Cmp::Ge(arg.value),
+                    "SCMP_CMP_MASKED_EQ" => Cmp::MaskedEq {
+                        mask: arg.value,
+                        value: arg.value_two,
+                    },
+                    op => {
+                        self.report(format!("{}.args[{}]", field, op));
+                        continue;
+                    }
+                };
+                if arg.index < 6 {
+                    args.push((arg.index, cmp));
+                } else {
+                    self.report(format!("{}.args[{}]", field, arg.index));
+                }
+            }
+
+            for name in &syscall.names {
+                // Names known for another architecture are skipped when the
+                // filter is compiled.
+                let known = [Arch::X86_64, Arch::Aarch64]
+                    .iter()
+                    .any(|arch| arch.syscall(name).is_some());
+                if !known {
+                    self.report(format!("linux.seccomp.syscalls[{}]", name));
+                    continue;
+                }
+                let rule = args.iter().fold(
+                    Rule::new(name.as_str(), action),
+                    |rule, &(i, cmp)| rule.arg(i, cmp),
+                );
+                filter = filter.rule(rule);
+            }
+        }
+        filter
+    }
+
+    fn action(
+        &mut self,
+        field: &str,
+        action: &str,
+        errno: Option<u16>,
+    ) -> Option<Action> {
+        let action = match action {
+            "SCMP_ACT_ALLOW" => Action::Allow,
+            "SCMP_ACT_ERRNO" => {
+                Action::Errno(errno.unwrap_or(libc::EPERM as u16))
+            }
+            "SCMP_ACT_LOG" => Action::Log,
+            "SCMP_ACT_TRAP" => Action::Trap,
+            "SCMP_ACT_KILL" | "SCMP_ACT_KILL_THREAD" => Action::KillThread,
+            "SCMP_ACT_KILL_PROCESS" => Action::KillProcess,
+            _ => {
+                self.report(field);
+                return None;
+            }
+        };
+        Some(action)
+    }
+}
+
+/// Parses a `tmpfs` size such as `65536k`.
+fn parse_size(size: &str) -> Option<u64> {
+    let (digits, shift) = match size.as_bytes().last()? {
+        b'k' | b'K' => (&size[..size.len() - 1], 10),
+        b'm' | b'M' => (&size[..size.len() - 1], 20),
+        b'g' | b'G' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// An error returned when a [`Bundle`] cannot be loaded.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LoadError {
+    /// The `config.json` file cannot be read.
+    Io(PathBuf, io::Error),
+    /// The `config.json` is malformed or is not a runtime configuration.
+    Json(serde_json::Error),
+    /// The `process.args` field is empty.
+    NoArgs,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(path, error) => {
+                write!(f, "cannot read {:?}: {}", path, error)
+            }
+            Self::Json(error) => {
+                write!(f, "invalid runtime configuration: {}", error)
+            }
+            Self::NoArgs => f.write_str("process.args is empty"),
+        }
+    }
+}
+
+impl error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(_, error) => Some(error),
+            Self::Json(error) => Some(error),
+            Self::NoArgs => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn translate(config: Value) -> Bundle {
+        Bundle::from_json("/bundle", &config.to_string()).unwrap()
+    }
+
+    fn config(extra: Value) -> Value {
+        let mut config = json!({
+            "ociVersion": "1.0.2",
+            "root": { "path": "rootfs" },
+            "process": { "args": ["sh", "-c", "true"] },
+        });
+        let object = config.as_object_mut().unwrap();
+        object.extend(extra.as_object().unwrap().clone());
+        config
+    }
+
+    /// Asserts that two configurations are equal, as far as `Debug` shows.
+    fn assert_same<T: fmt::Debug>(left: &T, right: &T) {
+        assert_eq!(format!("{:?}", left), format!("{:?}", right));
+    }
+
+    #[test]
+    fn supported_fields_are_translated() {
+        let bundle = translate(config(json!({
+            "process": {
+                "user": { "uid": 1000, "gid": 100, "additionalGids": [10] },
+                "args": ["make", "-j4"],
+                "env": ["PATH=/bin", "EMPTY="],
+                "cwd": "/build",
+                "capabilities": {
+                    "bounding": ["CAP_CHOWN", "CAP_KILL"],
+                    "effective": ["CAP_KILL"],
+                },
+                "rlimits": [
+                    { "type": "RLIMIT_NOFILE", "soft": 1024, "hard": 4096 },
+                ],
+                "noNewPrivileges": true,
+            },
+            "hostname": "sandbox",
+            "mounts": [
+                { "destination": "/proc", "type": "proc", "source": "proc" },
+                {
+                    "destination": "/dev",
+                    "type": "tmpfs",
+                    "options": ["nosuid", "strictatime", "mode=755"],
+                },
+                {
+                    "destination": "/tmp",
+                    "type": "tmpfs",
+                    "options": ["nosuid", "nodev", "size=64k"],
+                },
+                {
+                    "destination": "/src",
+                    "source": "src",
+                    "options": ["rbind", "ro"],
+                },
+            ],
+            "linux": {
+                "namespaces": [
+                    { "type": "pid" },
+                    { "type": "mount" },
+                    { "type": "uts" },
+                    { "type": "network" },
+                ],
+                "seccomp": {
+                    "defaultAction": "SCMP_ACT_ERRNO",
+                    "architectures": ["SCMP_ARCH_X86_64"],
+                    "syscalls": [{
+                        "names": ["personality"],
+                        "action": "SCMP_ACT_ALLOW",
+                        "args": [
+                            { "index": 0, "value": 8, "op": "SCMP_CMP_EQ" },
+                        ],
+                    }],
+                },
+            },
+        })));
+
+        let mut caps = CapSet::empty();
+        caps.insert(Capability::Kill);
+        let mut bounding = caps;
+        bounding.insert(Capability::Chown);
+        let filter = Filter::new(Action::Errno(libc::EPERM as u16))
+            .rule(Rule::new("personality", Action::Allow).arg(0, Cmp::Eq(8)));
+        let expected = Chroot::new()
+            .user_group("1000", "100")
+            .groups(&["10"])
+            .env("PATH", "/bin")
+            .env("EMPTY", "")
+            .current_dir("/build")
+            .bounding_caps(bounding)
+            .permitted_caps(CapSet::empty())
+            .effective_caps(caps)
+            .inheritable_caps(CapSet::empty())
+            .ambient_caps(CapSet::empty())
+            .rlimit(Resource::NoFile, 1024, 4096)
+            .no_new_privs()
+            .hostname("sandbox")
+            .mount_proc()
+            .minimal_dev()
+            .tmpfs("/tmp", 64 << 10)
+            .bind_ro("/bundle/src", "/src")
+            .pid_namespace()
+            .network(Network::None)
+            .seccomp(filter);
+
+        assert!(bundle.unsupported().is_empty());
+        assert_eq!(bundle.root(), Path::new("/bundle/rootfs"));
+        assert_eq!(bundle.args(), ["make", "-j4"]);
+        assert_same(bundle.chroot(), &expected);
+        assert!(bundle.cgroup().is_none());
+    }
+
+    #[test]
+    fn unsupported_fields_are_reported() {
+        let bundle = translate(config(json!({
+            "root": { "path": "rootfs", "readonly": true },
+            "process": {
+                "terminal": true,
+                "args": ["sh"],
+                "env": ["NOVALUE"],
+                "capabilities": { "bounding": ["CAP_NONSENSE"] },
+                "rlimits": [{ "type": "RLIMIT_BOGUS", "soft": 1, "hard": 1 }],
+                "apparmorProfile": "",
+                "oomScoreAdj": 100,
+            },
+            "hooks": { "prestart": [{ "path": "/bin/true" }] },
+            "annotations": { "key": "value" },
+            "mounts": [
+                { "destination": "/dev/pts", "type": "devpts" },
+                { "destination": "/data", "type": "bind" },
+                {
+                    "destination": "/tmp",
+                    "type": "tmpfs",
+                    "options": ["noexec", "size=lots"],
+                },
+                {
+                    "destination": "/run",
+                    "type": "tmpfs",
+                    "options": ["noexec"],
+                },
+            ],
+            "linux": {
+                "namespaces": [
+                    { "type": "cgroup" },
+                    { "type": "network", "path": "/proc/1/ns/net" },
+                    { "type": "uts" },
+                    { "type": "time" },
+                ],
+                "timeOffsets": { "monotonic": { "secs": -1 } },
+                "uidMappings": [
+                    { "containerID": 0, "hostID": 100000, "size": 65536 },
+                ],
+                "maskedPaths": ["/proc/kcore"],
+                "readonlyPaths": [],
+                "seccomp": {
+                    "defaultAction": "SCMP_ACT_NOTIFY",
+                    "syscalls": [
+                        { "names": ["read"], "action": "SCMP_ACT_TRACE" },
+                        {
+                            "names": ["write", "no_such_call"],
+                            "action": "SCMP_ACT_ALLOW",
+                            "args": [
+                                { "index": 6, "value": 0, "op": "SCMP_CMP_EQ" },
+                                { "index": 0, "value": 0, "op": "SCMP_CMP_XX" },
+                            ],
+                        },
+                    ],
+                },
+            },
+        })));
+
+        assert_eq!(
+            bundle.unsupported(),
+            [
+                "hooks",
+                "root.readonly",
+                "process.oomScoreAdj",
+                "process.terminal",
+                "process.env[NOVALUE]",
+                "process.capabilities.bounding[CAP_NONSENSE]",
+                "process.rlimits[RLIMIT_BOGUS]",
+                "mounts[/dev/pts]",
+                "mounts[/data].source",
+                "mounts[/tmp].options[size]",
+                "mounts[/run].options[noexec]",
+                "linux.namespaces[cgroup]",
+                "linux.namespaces[network].path",
+                "linux.namespaces[uts]",
+                "linux.timeOffsets",
+                "linux.uidMappings",
+                "linux.seccomp.defaultAction",
+                "linux.seccomp.syscalls[SCMP_ACT_TRACE]",
+                "linux.seccomp.syscalls[SCMP_ACT_ALLOW].args[6]",
+                "linux.seccomp.syscalls[SCMP_ACT_ALLOW].args[SCMP_CMP_XX]",
+                "linux.seccomp.syscalls[no_such_call]",
+                "linux.maskedPaths",
+            ]
+        );
+
+        // The write rule is kept without the unsupported comparisons.
+        let filter = Filter::new(Action::KillProcess)
+            .rule(Rule::new("write", Action::Allow));
+        let expected = Chroot::new()
+            .capabilities(CapSet::empty())
+            .tmpfs("/run", 0)
+            .seccomp(filter);
+        assert_same(bundle.chroot(), &expected);
+    }
+
+    #[test]
+    fn resources_are_translated_to_a_cgroup() {
+        let bundle = translate(config(json!({
+            "linux": {
+                "resources": {
+                    "memory": { "limit": 1 << 30, "swap": 0 },
+                    "pids": { "limit": 64 },
+                    "cpu": { "quota": 50000 },
+                    "blockIO": {
+                        "throttleReadBpsDevice": [
+                            { "major": 8, "minor": 0, "rate": 1000 },
+                        ],
+                        "throttleWriteIOPSDevice": [
+                            { "major": 8, "minor": 0, "rate": 10 },
+                            { "major": 8, "minor": 16, "rate": 20 },
+                        ],
+                    },
+                },
+            },
+        })));
+
+        let expected = Cgroup::new()
+            .memory_max(1 << 30)
+            .pids_max(64)
+            .cpu_max(Duration::from_millis(50), Duration::from_millis(100))
+            .io_max(IoMax::new(8, 0).read_bps(1000).write_iops(10))
+            .io_max(IoMax::new(8, 16).write_iops(20));
+        assert_eq!(bundle.unsupported(), ["linux.resources.memory.swap"]);
+        // Every cgroup is named uniquely.
+        let limits = |cgroup: &Cgroup| {
+            let debug = format!("{:?}", cgroup);
+            let name = debug.find("name: ").unwrap();
+            let end = name + debug[name..].find(", ").unwrap();
+            format!("{}{}", &debug[..name], &debug[end..])
+        };
+        assert_eq!(limits(bundle.cgroup().unwrap()), limits(&expected));
+
+        // Unlimited resources need no cgroup.
+        let unlimited = translate(config(json!({
+            "linux": { "resources": { "memory": { "limit": -1 } } },
+        })));
+        assert!(unlimited.cgroup().is_none());
+    }
+
+    #[test]
+    fn tmpfs_sizes_are_parsed() {
+        assert_eq!(parse_size("4096"), Some(4096));
+        assert_eq!(parse_size("64k"), Some(64 << 10));
+        assert_eq!(parse_size("2M"), Some(2 << 20));
+        assert_eq!(parse_size("1g"), Some(1 << 30));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("k"), None);
+        assert_eq!(parse_size("50%"), None);
+        assert_eq!(parse_size("17179869184g"), None);
+    }
+
+    #[test]
+    fn process_args_are_required() {
+        let config = config(json!({ "process": { "args": [] } }));
+        let result = Bundle::from_json("/bundle", &config.to_string());
+        assert!(matches!(result, Err(LoadError::NoArgs)));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{
+    mount::{self, MountOp, Step},
+    Error,
+};
+use std::{
+    ffi::CString,
+    fs::{self, Permissions},
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+/// A copy-on-write root directory backed by
+/// [overlayfs](https://www.kernel.org/doc/html/latest/filesystems/overlayfs.html).
+///
+/// The overlay is mounted over the `root` passed to
+/// [`Chroot::command`](crate::Chroot::command), which must be an existing
+/// directory, so that the lower directories are never modified.
+///
+/// Without an [`upper`](Self::upper) directory, changes are staged in a
+/// `tmpfs` mounted over the root directory first, which would hide a lower
+/// directory that is the root directory or within it. Such an overlay is
+/// reported as [`Error::Unsupported`].
+///
+/// # Examples
+///
+/// ```
+/// # return;
+/// use beach::{Chroot, Overlay};
+///
+/// let overlay =
+///     Overlay::new(&["/roots/base"]).upper("/build/upper", "/build/work");
+///
+/// Chroot::new()
+///     .overlay(overlay.clone())
+///     .command("/build/root", "make")
+///     .status()
+///     .unwrap();
+///
+/// for change in overlay.changes().unwrap() {
+///     println!("{:?} {}", change.kind, change.path.display());
+/// }
+/// overlay.discard().unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Overlay {
+    lower: Vec<PathBuf>,
+    upper: Option<(PathBuf, PathBuf)>,
+}
+
+/// A change made to an [`Overlay`], as recorded in its upper directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// The path relative to the root directory.
+    pub path: PathBuf,
+    /// What happened to the path.
+    pub kind: ChangeKind,
+}
+
+/// The kind of a [`Change`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    /// The path does not exist in any lower directory.
+    Added,
+    /// The path exists in a lower directory and was modified.
+    Modified,
+    /// The path was removed.
+    Removed,
+    /// The directory was removed and recreated, hiding the contents of the
+    /// lower directories.
+    Replaced,
+}
+
+impl Overlay {
+    /// Creates an overlay of the read-only `lower` directories, listed from
+    /// top to bottom.
+    ///
+    /// Changes are written to a `tmpfs` and discarded when the command exits,
+    /// unless [`upper`](Self::upper) is used.
+    pub fn new<I>(lower: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        Self {
+            lower: lower
+                .into_iter()
+                .map(|dir| dir.as_ref().to_owned())
+                .collect(),
+            upper: None,
+        }
+    }
+
+    /// Writes changes to the `upper` directory, using `work` as scratch
+    /// space.
+    ///
+    /// Both must be empty directories on the same filesystem when first used.
+    pub fn upper<U, W>(mut self, upper: U, work: W) -> Self
+    where
+        U: AsRef<Path>,
+        W: AsRef<Path>,
+    {
+        self.upper =
+            Some((upper.as_ref().to_owned(), work.as_ref().to_owned()));
+        self
+    }
+
+    /// Returns the directories that make up the overlay, from top to bottom.
+    pub(crate) fn layers(&self) -> impl Iterator<Item = &Path> {
+        let upper = self.upper.as_ref().map(|(upper, _)| upper.as_path());
+        upper
+            .into_iter()
+            .chain(self.lower.iter().map(PathBuf::as_path))
+    }
+
+    /// Returns whether the upper directory is staged in a `tmpfs`, so that
+    /// changes are discarded.
+    pub(crate) fn is_transient(&self) -> bool {
+        self.upper.is_none()
+    }
+
+    /// Prepares the steps that mount the overlay over `root`.
+    pub(crate) fn prepare(
+        &self,
+        root: &Path,
+        unprivileged: bool,
+    ) -> Result<Vec<Step>, Error> {
+        if self.lower.is_empty() {
+            return Err(Error::Unsupported(
+                "an overlay without lower directories",
+            ));
+        }
+
+        let mut steps = Vec::new();
+        let (upper, work) = match &self.upper {
+            Some((upper, work)) => (upper.clone(), work.clone()),
+            None => {
+                let within = canonical(root);
+                let hidden = self
+                    .lower
+                    .iter()
+                    .any(|lower| canonical(lower).starts_with(&within));
+                if hidden {
+                    return Err(Error::Unsupported(
+                        "a lower directory within the root of an overlay \
+                         without an upper directory",
+                    ));
+                }
+
+                // Stage the upper directory in a `tmpfs` that the overlay
+                // then covers.
+                steps.push(Step::Mount(MountOp::tmpfs(root, "mode=755")?));
+                let (upper, work) = (root.join(".upper"), root.join(".work"));
+                steps.push(Step::Mkdir(mount::cstring(&upper)?));
+                steps.push(Step::Mkdir(mount::cstring(&work)?));
+                (upper, work)
+            }
+        };
+
+        let mut data = b"lowerdir=".to_vec();
+        for (i, lower) in self.lower.iter().enumerate() {
+            if i != 0 {
+                data.push(b':');
+            }
+            data.extend_from_slice(option_path(lower)?);
+        }
+        data.extend_from_slice(b",upperdir=");
+        data.extend_from_slice(option_path(&upper)?);
+        data.extend_from_slice(b",workdir=");
+        data.extend_from_slice(option_path(&work)?);
+        if unprivileged {
+            data.extend_from_slice(b",userxattr");
+        }
+
+        let data = CString::new(data).map_err(io::Error::from)?;
+        steps.push(Step::Mount(MountOp::overlay(root, data)?));
+        Ok(steps)
+    }
+
+    /// Returns the changes recorded in the upper directory.
+    ///
+    /// This fails if the overlay has no [`upper`](Self::upper) directory.
+    pub fn changes(&self) -> io::Result<Vec<Change>> {
+        let (upper, _) = self.upper.as_ref().ok_or_else(no_upper)?;
+        let mut changes = Vec::new();
+        self.collect(upper, Path::new(""), false, &mut changes)?;
+        Ok(changes)
+    }
+
+    /// Collects changes under `dir`, where `hidden` means the lower
+    /// directories are not visible.
+    fn collect(
+        &self,
+        upper: &Path,
+        dir: &Path,
+        hidden: bool,
+        changes: &mut Vec<Change>,
+    ) -> io::Result<()> {
+        for entry in fs::read_dir(upper.join(dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            let in_lower = !hidden && self.in_lower(&path);
+
+            if file_type.is_dir() {
+                let opaque = in_lower && is_opaque(&entry.path());
+
+                // Directories that are only in the upper directory because
+                // their contents changed are not changes themselves.
+                if opaque || !in_lower {
+                    changes.push(Change {
+                        path: path.clone(),
+                        kind: if opaque {
+                            ChangeKind::Replaced
+                        } else {
+                            ChangeKind::Added
+                        },
+                    });
+                }
+
+                self.collect(upper, &path, opaque || !in_lower, changes)?;
+                continue;
+            }
+
+            let kind = if file_type.is_char_device() && is_whiteout(&entry)? {
+                ChangeKind::Removed
+            } else if in_lower {
+                ChangeKind::Modified
+            } else {
+                ChangeKind::Added
+            };
+
+            changes.push(Change { path, kind });
+        }
+        Ok(())
+    }
+
+    fn in_lower(&self, path: &Path) -> bool {
+        self.lower
+            .iter()
+            .any(|lower| fs::symlink_metadata(lower.join(path)).is_ok())
+    }
+
+    /// Removes the contents of the upper and work directories, discarding all
+    /// changes.
+    pub fn discard(&self) -> io::Result<()> {
+        let (upper, work) = self.upper.as_ref().ok_or_else(no_upper)?;
+        for dir in &[upper, work] {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if fs::symlink_metadata(&path)?.is_dir() {
+                    // overlayfs leaves `work/work` without any permissions.
+                    let _ = fs::set_permissions(
+                        &path,
+                        Permissions::from_mode(0o700),
+                    );
+                    fs::remove_dir_all(path)?;
+                } else {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn no_upper() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "overlay has no upper directory")
+}
+
+/// Returns `path` with symbolic links resolved, or as given if it does not
+/// exist.
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+/// Returns `path` for use in overlayfs mount options, which cannot escape
+/// separators.
+fn option_path(path: &Path) -> Result<&[u8], Error> {
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.iter().any(|b| b":,".contains(b)) {
+        return Err(Error::Unsupported("overlay paths containing ':' or ','"));
+    }
+    Ok(bytes)
+}
+
+/// Whiteouts are character devices with device number 0/0.
+fn is_whiteout(entry: &fs::DirEntry) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(entry.metadata()?.rdev() == 0)
+}
+
+/// Opaque directories hide the contents of lower directories.
+fn is_opaque(path: &Path) -> bool {
+    let path = match mount::cstring(path) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    [&b"trusted.overlay.opaque\0"[..], b"user.overlay.opaque\0"]
+        .iter()
+        .any(|name| {
+            let mut value = 0u8;
+            let len = unsafe {
+                libc::lgetxattr(
+                    path.as_ptr(),
+                    name.as_ptr().cast(),
+                    (&mut value as *mut u8).cast(),
+                    1,
+                )
+            };
+            len == 1 && value == b'y'
+        })
+}
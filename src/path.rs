@@ -0,0 +1,93 @@
+use std::{
+    ffi::OsStr,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+/// The maximum number of symbolic links followed, matching `MAXSYMLINKS`.
+const MAX_SYMLINKS: usize = 40;
+
+/// Resolves `path` as if `root` were `/`, returning the corresponding path on
+/// the host.
+///
+/// Symbolic links are followed without escaping `root`, so an absolute link
+/// target is interpreted relative to `root`.
+pub(crate) fn resolve_in_root(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    let mut resolved = PathBuf::from(root);
+    let mut depth = 0;
+    let mut links = 0;
+    let mut pending: Vec<PathBuf> = vec![path.to_owned()];
+
+    while let Some(path) = pending.pop() {
+        let mut components = path.components();
+        while let Some(component) = components.next() {
+            match component {
+                Component::RootDir | Component::Prefix(_) => {
+                    resolved = PathBuf::from(root);
+                    depth = 0;
+                }
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    // `..` at the root stays at the root.
+                    if depth > 0 {
+                        resolved.pop();
+                        depth -= 1;
+                    }
+                }
+                Component::Normal(name) => {
+                    let next = resolved.join(name);
+                    let is_link = fs::symlink_metadata(&next)
+                        .map(|meta| meta.file_type().is_symlink())
+                        .unwrap_or(false);
+
+                    if !is_link {
+                        resolved = next;
+                        depth += 1;
+                        continue;
+                    }
+
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(io::Error::from_raw_os_error(libc::ELOOP));
+                    }
+
+                    // Resolve the link target, then whatever remains.
+                    pending.push(components.as_path().to_owned());
+                    pending.push(fs::read_link(&next)?);
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Returns whether `program` names an executable file within `root`,
+/// searching the colon-separated `search_path` if it has no slashes.
+pub(crate) fn find_program(
+    root: &Path,
+    program: &OsStr,
+    search_path: &OsStr,
+) -> Option<PathBuf> {
+    if program.as_bytes().contains(&b'/') {
+        return resolve_executable(root, Path::new(program));
+    }
+
+    std::env::split_paths(search_path)
+        .find_map(|dir| resolve_executable(root, &dir.join(program)))
+}
+
+fn resolve_executable(root: &Path, path: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = resolve_in_root(root, path).ok()?;
+    let meta = fs::metadata(&path).ok()?;
+
+    if meta.is_file() && meta.permissions().mode() & 0o111 != 0 {
+        Some(path)
+    } else {
+        None
+    }
+}
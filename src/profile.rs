@@ -0,0 +1,472 @@
+//! Reusable [`Chroot`] configurations that can be stored as TOML or JSON.
+//!
+//! A [`SandboxProfile`] describes how a command is sandboxed, independently
+//! of the root directory and program, so that the same profile can be kept
+//! in a package manifest and used for every build of a package.
+//!
+//! This module requires the `serde` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use beach::profile::SandboxProfile;
+//!
+//! let profile = SandboxProfile::from_toml(r#"
+//!     user = "build:build"
+//!     network = "loopback"
+//!     pid-namespace = true
+//!     pass-env = ["TERM"]
+//!
+//!     [env]
+//!     HOME = "/build"
+//!
+//!     [rlimits]
+//!     no-file = { soft = 1024 }
+//!     cpu = { soft = 600, hard = 660 }
+//!     core = { soft = 0, hard = "unlimited" }
+//!
+//!     [[mounts]]
+//!     type = "bind"
+//!     source = "/home/me/src"
+//!     target = "/build"
+//!
+//!     [[mounts]]
+//!     type = "proc"
+//! "#).unwrap();
+//!
+//! # return;
+//! profile.command("/path/to/root", "make").spawn().unwrap();
+//! ```
+
+use crate::{Backend, Chroot, Error, Network, Resource};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error,
+    ffi::OsStr,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+/// A serializable [`Chroot`] configuration.
+///
+/// Every field is optional when deserializing and defaults to the behavior
+/// of [`Chroot::new`]. Field names are in kebab-case, and unknown fields are
+/// rejected so that typos do not silently weaken the sandbox.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct SandboxProfile {
+    /// See [`Chroot::backend`].
+    pub backend: Backend,
+    /// See [`Chroot::unprivileged`].
+    pub unprivileged: bool,
+    /// The user to run as, optionally followed by a colon and the group,
+    /// such as `build:build`. See [`Chroot::user_group`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// See [`Chroot::groups`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// See [`Chroot::skip_chdir`].
+    pub skip_chdir: bool,
+    /// See [`Chroot::current_dir`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_dir: Option<PathBuf>,
+    /// Mounts made within the root directory, in order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<Mount>,
+    /// See [`Chroot::inherit_env`].
+    pub inherit_env: bool,
+    /// See [`Chroot::pass_env`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pass_env: Vec<String>,
+    /// See [`Chroot::env`].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// See [`Chroot::rlimit`].
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub rlimits: BTreeMap<Resource, Limit>,
+    /// See [`Chroot::no_new_privs`].
+    pub no_new_privs: bool,
+    /// See [`Chroot::network`].
+    pub network: Network,
+    /// See [`Chroot::pid_namespace`].
+    pub pid_namespace: bool,
+    /// See [`Chroot::ipc_namespace`].
+    pub ipc_namespace: bool,
+    /// See [`Chroot::time_namespace`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_namespace: Option<TimeOffsets>,
+    /// See [`Chroot::hostname`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// See [`Chroot::domainname`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domainname: Option<String>,
+}
+
+/// A mount in a [`SandboxProfile`], tagged by its `type`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields, rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Mount {
+    /// See [`Chroot::bind`] and [`Chroot::bind_ro`].
+    #[serde(rename_all = "kebab-case")]
+    Bind {
+        /// The path on the host.
+        source: PathBuf,
+        /// The path within the root directory.
+        target: PathBuf,
+        /// Whether the mount is read-only.
+        #[serde(default)]
+        read_only: bool,
+    },
+    /// See [`Chroot::tmpfs`].
+    Tmpfs {
+        /// The path within the root directory.
+        target: PathBuf,
+        /// The size limit in bytes, or 0 for half of the memory.
+        #[serde(default)]
+        size: u64,
+    },
+    /// See [`Chroot::mount_proc`].
+    Proc,
+    /// See [`Chroot::mount_sys`].
+    Sys,
+    /// See [`Chroot::minimal_dev`].
+    Dev,
+}
+
+/// A resource limit in a [`SandboxProfile`].
+///
+/// A limit of `u64::MAX` is unlimited, and is written as `"unlimited"` or
+/// `"infinity"`, since TOML integers are signed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    /// The soft limit.
+    #[serde(with = "limit")]
+    pub soft: u64,
+    /// The hard limit, which is the soft limit if unset.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "limit::option"
+    )]
+    pub hard: Option<u64>,
+}
+
+/// The clock offsets of a time namespace in a [`SandboxProfile`], in
+/// seconds.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeOffsets {
+    /// The offset of the monotonic clock.
+    pub monotonic: u64,
+    /// The offset of the boot-time clock.
+    pub boottime: u64,
+}
+
+impl SandboxProfile {
+    /// Parses a profile from TOML.
+    pub fn from_toml(toml: &str) -> Result<Self, LoadError> {
+        toml::from_str(toml).map_err(LoadError::Toml)
+    }
+
+    /// Parses a profile from JSON.
+    pub fn from_json(json: &str) -> Result<Self, LoadError> {
+        serde_json::from_str(json).map_err(LoadError::Json)
+    }
+
+    /// Loads a profile from a `.toml` or `.json` file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let parse = match path.extension().and_then(OsStr::to_str) {
+            Some("toml") => Self::from_toml,
+            Some("json") => Self::from_json,
+            _ => return Err(LoadError::UnknownFormat(path.to_owned())),
+        };
+        let contents = fs::read_to_string(path)
+            .map_err(|error| LoadError::Io(path.to_owned(), error))?;
+        parse(&contents)
+    }
+
+    /// Serializes the profile to TOML.
+    ///
+    /// This fails if a number other than an unlimited [`Limit`] exceeds
+    /// `i64::MAX`, which TOML cannot represent.
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+
+    /// Serializes the profile to pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("profiles are valid JSON")
+    }
+
+    /// Returns a [`Chroot`] configured by the profile.
+    pub fn chroot(&self) -> Chroot {
+        let mut chroot = Chroot::new().backend(self.backend);
+        if self.unprivileged {
+            chroot = chroot.unprivileged();
+        }
+        if let Some(user) = &self.user {
+            chroot = match user.split_once(':') {
+                Some((user, group)) => chroot.user_group(user, group),
+                None => chroot.user(user),
+            };
+        }
+        chroot = chroot.groups(&self.groups);
+        if self.skip_chdir {
+            chroot = chroot.skip_chdir();
+        }
+        if let Some(dir) = &self.current_dir {
+            chroot = chroot.current_dir(dir);
+        }
+
+        for mount in &self.mounts {
+            chroot = match mount {
+                Mount::Bind {
+                    source,
+                    target,
+                    read_only: false,
+                } => chroot.bind(source, target),
+                Mount::Bind {
+                    source,
+                    target,
+                    read_only: true,
+                } => chroot.bind_ro(source, target),
+                Mount::Tmpfs { target, size } => chroot.tmpfs(target, *size),
+                Mount::Proc => chroot.mount_proc(),
+                Mount::Sys => chroot.mount_sys(),
+                Mount::Dev => chroot.minimal_dev(),
+            };
+        }
+
+        if self.inherit_env {
+            chroot = chroot.inherit_env();
+        }
+        for key in &self.pass_env {
+            chroot = chroot.pass_env(key);
+        }
+        chroot = chroot.envs(&self.env);
+
+        for (&resource, limit) in &self.rlimits {
+            let hard = limit.hard.unwrap_or(limit.soft);
+            chroot = chroot.rlimit(resource, limit.soft, hard);
+        }
+        if self.no_new_privs {
+            chroot = chroot.no_new_privs();
+        }
+
+        chroot = chroot.network(self.network);
+        if self.pid_namespace {
+            chroot = chroot.pid_namespace();
+        }
+        if self.ipc_namespace {
+            chroot = chroot.ipc_namespace();
+        }
+        if let Some(offsets) = self.time_namespace {
+            chroot = chroot.time_namespace(
+                Duration::from_secs(offsets.monotonic),
+                Duration::from_secs(offsets.boottime),
+            );
+        }
+        if let Some(hostname) = &self.hostname {
+            chroot = chroot.hostname(hostname);
+        }
+        if let Some(domainname) = &self.domainname {
+            chroot = chroot.domainname(domainname);
+        }
+        chroot
+    }
+
+    /// Returns a `Command` for `program` sandboxed by the profile.
+    ///
+    /// See [`Chroot::command`].
+    pub fn command<R, P>(&self, root: R, program: P) -> Command
+    where
+        R: AsRef<Path>,
+        P: AsRef<OsStr>,
+    {
+        self.chroot().command(root, program)
+    }
+
+    /// Returns a `Command` for `program` sandboxed by the profile, or an
+    /// error if it would fail to start.
+    ///
+    /// See [`Chroot::try_command`].
+    pub fn try_command<R, P>(
+        &self,
+        root: R,
+        program: P,
+    ) -> Result<Command, Error>
+    where
+        R: AsRef<Path>,
+        P: AsRef<OsStr>,
+    {
+        self.chroot().try_command(root, program)
+    }
+}
+
+/// Serializes a limit of `u64::MAX` as `"unlimited"`.
+mod limit {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Number(u64),
+        Word(Word),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Word {
+        #[serde(alias = "infinity")]
+        Unlimited,
+    }
+
+    impl From<u64> for Value {
+        fn from(limit: u64) -> Self {
+            match limit {
+                u64::MAX => Self::Word(Word::Unlimited),
+                limit => Self::Number(limit),
+            }
+        }
+    }
+
+    impl From<Value> for u64 {
+        fn from(value: Value) -> Self {
+            match value {
+                Value::Number(limit) => limit,
+                Value::Word(Word::Unlimited) => u64::MAX,
+            }
+        }
+    }
+
+    pub fn serialize<S: Serializer>(
+        limit: &u64,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Value::from(*limit).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<u64, D::Error> {
+        Value::deserialize(deserializer).map(u64::from)
+    }
+
+    pub mod option {
+        use super::Value;
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            limit: &Option<u64>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            limit.map(Value::from).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<u64>, D::Error> {
+            let value = Option::<Value>::deserialize(deserializer)?;
+            Ok(value.map(u64::from))
+        }
+    }
+}
+
+/// An error returned when a [`SandboxProfile`] cannot be loaded.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum LoadError {
+    /// The file has neither a `.toml` nor a `.json` extension.
+    UnknownFormat(PathBuf),
+    /// The file cannot be read.
+    Io(PathBuf, io::Error),
+    /// The TOML is malformed or does not describe a profile.
+    Toml(toml::de::Error),
+    /// The JSON is malformed or does not describe a profile.
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownFormat(path) => {
+                write!(f, "profile {:?} is neither TOML nor JSON", path)
+            }
+            Self::Io(path, error) => {
+                write!(f, "cannot read profile {:?}: {}", path, error)
+            }
+            Self::Toml(error) => write!(f, "invalid profile: {}", error),
+            Self::Json(error) => write!(f, "invalid profile: {}", error),
+        }
+    }
+}
+
+impl error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::UnknownFormat(_) => None,
+            Self::Io(_, error) => Some(error),
+            Self::Toml(error) => Some(error),
+            Self::Json(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_limits_round_trip() {
+        let mut profile = SandboxProfile::default();
+        let unlimited = Limit {
+            soft: u64::MAX,
+            hard: None,
+        };
+        let core = Limit {
+            soft: 0,
+            hard: Some(u64::MAX),
+        };
+        profile.rlimits.insert(Resource::Stack, unlimited);
+        profile.rlimits.insert(Resource::Core, core);
+
+        let toml = profile.to_toml().unwrap();
+        assert!(toml.contains(r#"soft = "unlimited""#), "{}", toml);
+        assert_eq!(SandboxProfile::from_toml(&toml).unwrap(), profile);
+        let json = profile.to_json();
+        assert_eq!(SandboxProfile::from_json(&json).unwrap(), profile);
+
+        let parsed = SandboxProfile::from_toml(
+            "rlimits = { stack = { soft = \"infinity\", hard = 8 } }",
+        )
+        .unwrap();
+        let stack = parsed.rlimits[&Resource::Stack];
+        assert_eq!(stack.soft, u64::MAX);
+        assert_eq!(stack.hard, Some(8));
+        assert!(SandboxProfile::from_toml(
+            "rlimits = { stack = { soft = \"lots\" } }"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn numbers_beyond_toml_are_an_error() {
+        let mut profile = SandboxProfile::default();
+        profile.mounts.push(Mount::Tmpfs {
+            target: "/tmp".into(),
+            size: u64::MAX,
+        });
+        assert!(profile.to_toml().is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::{fmt, io, ptr};
+
+/// A resource whose use can be limited with
+/// [`Chroot::rlimit`](crate::Chroot::rlimit).
+///
+/// See [`setrlimit(2)`](http://man7.org/linux/man-pages/man2/setrlimit.2.html)
+/// for the exact meaning of each limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+#[non_exhaustive]
+pub enum Resource {
+    /// CPU time in seconds, after which `SIGXCPU` and then `SIGKILL` are
+    /// sent.
+    Cpu,
+    /// The size of files that can be created, in bytes.
+    FileSize,
+    /// The size of the data segment, in bytes.
+    Data,
+    /// The size of the stack, in bytes.
+    Stack,
+    /// The size of core dumps, in bytes. 0 disables them.
+    Core,
+    /// The number of open file descriptors.
+    NoFile,
+    /// The size of the virtual address space, in bytes.
+    AddressSpace,
+    /// The number of processes of the real user ID.
+    NProc,
+    /// The amount of memory that can be locked, in bytes.
+    MemLock,
+}
+
+impl Resource {
+    /// Every resource, in order.
+    pub const ALL: &'static [Resource] = &[
+        Self::Cpu,
+        Self::FileSize,
+        Self::Data,
+        Self::Stack,
+        Self::Core,
+        Self::NoFile,
+        Self::AddressSpace,
+        Self::NProc,
+        Self::MemLock,
+    ];
+
+    /// Returns the name of the limit, such as `RLIMIT_CPU`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Cpu => "RLIMIT_CPU",
+            Self::FileSize => "RLIMIT_FSIZE",
+            Self::Data => "RLIMIT_DATA",
+            Self::Stack => "RLIMIT_STACK",
+            Self::Core => "RLIMIT_CORE",
+            Self::NoFile => "RLIMIT_NOFILE",
+            Self::AddressSpace => "RLIMIT_AS",
+            Self::NProc => "RLIMIT_NPROC",
+            Self::MemLock => "RLIMIT_MEMLOCK",
+        }
+    }
+
+    fn raw(self) -> libc::c_int {
+        let raw = match self {
+            Self::Cpu => libc::RLIMIT_CPU,
+            Self::FileSize => libc::RLIMIT_FSIZE,
+            Self::Data => libc::RLIMIT_DATA,
+            Self::Stack => libc::RLIMIT_STACK,
+            Self::Core => libc::RLIMIT_CORE,
+            Self::NoFile => libc::RLIMIT_NOFILE,
+            Self::AddressSpace => libc::RLIMIT_AS,
+            Self::NProc => libc::RLIMIT_NPROC,
+            Self::MemLock => libc::RLIMIT_MEMLOCK,
+        };
+        raw as libc::c_int
+    }
+}
+
+impl fmt::Display for Resource {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A soft and hard limit on a [`Resource`], where `u64::MAX` is unlimited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Rlimit {
+    pub resource: Resource,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+/// `struct rlimit64` as used by `prlimit64(2)`, which is the same on every
+/// target, unlike `struct rlimit`.
+#[repr(C)]
+struct Rlimit64 {
+    soft: u64,
+    hard: u64,
+}
+
+impl Rlimit {
+    /// Applies the limit to the calling process.
+    ///
+    /// Only async-signal-safe operations are used.
+    pub fn apply(&self) -> io::Result<()> {
+        let new = Rlimit64 {
+            soft: self.soft,
+            hard: self.hard,
+        };
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_prlimit64,
+                0,
+                self.resource.raw(),
+                &new,
+                ptr::null_mut::<Rlimit64>(),
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Returns whether applying the limit raises the current hard limit,
+    /// which requires `CAP_SYS_RESOURCE`.
+    pub fn raises_hard(&self) -> io::Result<bool> {
+        let mut old = Rlimit64 { soft: 0, hard: 0 };
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_prlimit64,
+                0,
+                self.resource.raw(),
+                ptr::null::<Rlimit64>(),
+                &mut old,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(self.hard > old.hard)
+    }
+}
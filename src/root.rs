@@ -0,0 +1,693 @@
+use crate::elf::Elf;
+use std::{
+    collections::HashSet,
+    env, error,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, PermissionsExt},
+    },
+    path::{Component, Path, PathBuf},
+};
+
+/// The search path used when `PATH` is unset.
+const DEFAULT_PATH: &str = "/bin:/usr/bin";
+
+/// The maximum number of symbolic links followed, matching `MAXSYMLINKS`.
+const MAX_SYMLINKS: usize = 40;
+
+/// Builds a minimal root directory from executables of the host.
+///
+/// Each executable is placed at the same path within the root, along with
+/// the dynamic loader and the shared libraries it needs. Libraries are found
+/// the way the loader finds them: through `DT_RPATH` and `DT_RUNPATH`
+/// (expanding `$ORIGIN`), then the directories listed in `/etc/ld.so.conf`,
+/// then the default directories. `LD_LIBRARY_PATH` is ignored. Symbolic
+/// links on the way to a file, such as `/lib -> usr/lib`, are recreated so
+/// that the root has the same layout as the host.
+///
+/// Libraries loaded with `dlopen`, such as NSS modules, cannot be found
+/// this way and must be added with [`library`](Self::library).
+///
+/// # Examples
+///
+/// ```
+/// # return;
+/// use beach::{Chroot, RootBuilder};
+///
+/// RootBuilder::new()
+///     .binaries(&["sh", "make"])
+///     .etc_stubs()
+///     .build("/build/root")
+///     .unwrap();
+///
+/// Chroot::new().command("/build/root", "make").status().unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RootBuilder {
+    binaries: Vec<OsString>,
+    libraries: Vec<String>,
+    hardlink: bool,
+    etc_stubs: bool,
+    users: Vec<(String, u32, u32)>,
+}
+
+impl RootBuilder {
+    /// Creates a builder that adds nothing to the root.
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            binaries: Vec::new(),
+            libraries: Vec::new(),
+            hardlink: false,
+            etc_stubs: false,
+            users: Vec::new(),
+        }
+    }
+
+    /// Adds an executable, which is searched for in the host's `PATH` if it
+    /// does not contain a slash.
+    ///
+    /// Scripts are added along with the interpreter named by their `#!` line.
+    pub fn binary<S: AsRef<OsStr>>(mut self, binary: S) -> Self {
+        self.binaries.push(binary.as_ref().to_owned());
+        self
+    }
+
+    /// Adds several executables.
+    ///
+    /// See [`binary`](Self::binary).
+    pub fn binaries<I>(mut self, binaries: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        self.binaries.extend(
+            binaries
+                .into_iter()
+                .map(|binary| binary.as_ref().to_owned()),
+        );
+        self
+    }
+
+    /// Adds a shared library by its name, such as `libnss_files.so.2`, along
+    /// with the libraries it needs.
+    ///
+    /// This is for libraries loaded with `dlopen`, which are not listed as
+    /// dependencies.
+    pub fn library<S: Into<String>>(mut self, name: S) -> Self {
+        self.libraries.push(name.into());
+        self
+    }
+
+    /// Hard-links files into the root instead of copying them.
+    ///
+    /// Files are still copied when linking fails, such as when the root is on
+    /// another filesystem. Linked files share their contents and permissions
+    /// with the host, so the root should be mounted read-only.
+    pub fn hardlink(mut self) -> Self {
+        self.hardlink = true;
+        self
+    }
+
+    /// Adds minimal `/etc/passwd`, `/etc/group`, `/etc/hosts` and
+    /// `/etc/nsswitch.conf` files.
+    ///
+    /// The user and group databases contain `root` and `nobody`, and any
+    /// users added with [`user`](Self::user). Existing files are kept.
+    pub fn etc_stubs(mut self) -> Self {
+        self.etc_stubs = true;
+        self
+    }
+
+    /// Adds a user and a group of the same name to the `/etc` stubs.
+    ///
+    /// This implies [`etc_stubs`](Self::etc_stubs).
+    pub fn user<S: Into<String>>(
+        mut self,
+        name: S,
+        uid: u32,
+        gid: u32,
+    ) -> Self {
+        self.etc_stubs = true;
+        self.users.push((name.into(), uid, gid));
+        self
+    }
+
+    /// Populates `root`, creating it if needed.
+    ///
+    /// Files that already exist within the root are kept, so that building
+    /// again adds to it.
+    pub fn build<P: AsRef<Path>>(&self, root: P) -> Result<(), BuildError> {
+        let root = root.as_ref();
+        fs::create_dir_all(root).map_err(io_error(root))?;
+
+        let mut build = Build {
+            options: self,
+            root,
+            search_dirs: ld_so_conf()?,
+            done: HashSet::new(),
+        };
+        for binary in &self.binaries {
+            let path = find_binary(binary)?;
+            build.binary(&path, 0)?;
+        }
+        for library in &self.libraries {
+            build.library(library)?;
+        }
+        if self.etc_stubs {
+            self.write_etc_stubs(root)?;
+        }
+        Ok(())
+    }
+
+    fn write_etc_stubs(&self, root: &Path) -> Result<(), BuildError> {
+        let mut passwd = String::from("root:x:0:0:root:/root:/bin/sh\n");
+        let mut group = String::from("root:x:0:\n");
+        for (name, uid, gid) in &self.users {
+            passwd +=
+                &format!("{}:x:{}:{}:{}:/:/bin/sh\n", name, uid, gid, name);
+            group += &format!("{}:x:{}:\n", name, gid);
+        }
+        passwd += "nobody:x:65534:65534:nobody:/nonexistent:/bin/false\n";
+        group += "nogroup:x:65534:\n";
+
+        let stubs = [
+            ("passwd", passwd.as_str()),
+            ("group", group.as_str()),
+            ("hosts", "127.0.0.1 localhost\n::1 localhost\n"),
+            (
+                "nsswitch.conf",
+                "passwd: files\ngroup: files\nhosts: files dns\n",
+            ),
+        ];
+        let etc = root.join("etc");
+        fs::create_dir_all(&etc).map_err(io_error(&etc))?;
+        for (name, contents) in &stubs {
+            let path = etc.join(name);
+            let file =
+                OpenOptions::new().write(true).create_new(true).open(&path);
+            match file {
+                Ok(mut file) => {
+                    file.write_all(contents.as_bytes())
+                        .map_err(io_error(&path))?;
+                }
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(error) => return Err(BuildError::Io(path, error)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of a [`RootBuilder::build`].
+struct Build<'a> {
+    options: &'a RootBuilder,
+    root: &'a Path,
+    /// The directories from `/etc/ld.so.conf`.
+    search_dirs: Vec<PathBuf>,
+    /// The host files whose dependencies were added.
+    done: HashSet<PathBuf>,
+}
+
+impl Build<'_> {
+    /// Adds an executable at `path` and what it needs to run.
+    fn binary(&mut self, path: &Path, depth: usize) -> Result<(), BuildError> {
+        let real = self.place(path)?;
+        if !self.done.insert(real.clone()) {
+            return Ok(());
+        }
+        if let Some(elf) = Elf::read(&real).map_err(io_error(&real))? {
+            return self.dependencies(&real, &elf, &[]);
+        }
+
+        // Interpreters may be scripts themselves, but not endlessly.
+        let words = shebang(&real).map_err(io_error(&real))?;
+        let interpreter = match words.first() {
+            Some(interpreter) if depth < MAX_SYMLINKS => Path::new(interpreter),
+            _ => return Err(BuildError::NotExecutable(path.to_owned())),
+        };
+        self.binary(interpreter, depth + 1)?;
+
+        // `#!/usr/bin/env bash` runs the program named after `env`.
+        let program = words.get(1).filter(|word| !word.starts_with('-'));
+        match program {
+            Some(program) if interpreter.ends_with("env") => {
+                let program = find_binary(OsStr::new(program))?;
+                self.binary(&program, depth + 1)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds the library `name`, searching only the system directories.
+    fn library(&mut self, name: &str) -> Result<(), BuildError> {
+        let class64 = cfg!(target_pointer_width = "64");
+        let found = self
+            .system_dirs(class64)
+            .into_iter()
+            .map(|dir| dir.join(name))
+            .find(|path| match Elf::read(path) {
+                Ok(Some(library)) => library.class64 == class64,
+                _ => false,
+            });
+        let path = found.ok_or_else(|| {
+            BuildError::LibraryNotFound(name.to_owned(), None)
+        })?;
+        self.object(&path, &[])
+    }
+
+    /// Adds the ELF object at `path` and its dependencies.
+    ///
+    /// `inherited` is the `DT_RPATH` of the objects that loaded it.
+    fn object(
+        &mut self,
+        path: &Path,
+        inherited: &[PathBuf],
+    ) -> Result<(), BuildError> {
+        let real = self.place(path)?;
+        if !self.done.insert(real.clone()) {
+            return Ok(());
+        }
+        match Elf::read(&real).map_err(io_error(&real))? {
+            Some(elf) => self.dependencies(&real, &elf, inherited),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds the loader and libraries of `elf`, which is at `path`.
+    fn dependencies(
+        &mut self,
+        path: &Path,
+        elf: &Elf,
+        inherited: &[PathBuf],
+    ) -> Result<(), BuildError> {
+        if let Some(interp) = &elf.interp {
+            self.object(interp, &[])?;
+        }
+
+        let origin = path.parent().unwrap_or_else(|| Path::new("/"));
+        let expand = |list: &Option<String>| -> Vec<PathBuf> {
+            let list = list.as_deref().unwrap_or("");
+            list.split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| expand_dir(dir, origin, elf.class64))
+                .collect()
+        };
+        let runpath = expand(&elf.runpath);
+
+        // `DT_RPATH` is ignored in the presence of `DT_RUNPATH`, and applies
+        // to the libraries loaded through the object too.
+        let mut rpath = Vec::new();
+        if elf.runpath.is_none() {
+            rpath = expand(&elf.rpath);
+            rpath.extend_from_slice(inherited);
+        }
+
+        for name in &elf.needed {
+            let found = if name.contains('/') {
+                Some(origin.join(name))
+            } else {
+                let system = self.system_dirs(elf.class64);
+                rpath
+                    .iter()
+                    .chain(&runpath)
+                    .chain(&system)
+                    .map(|dir| dir.join(name))
+                    .find(|path| match Elf::read(path) {
+                        Ok(Some(library)) => elf.is_compatible(&library),
+                        _ => false,
+                    })
+            };
+            let found = found.ok_or_else(|| {
+                BuildError::LibraryNotFound(name.clone(), Some(path.to_owned()))
+            })?;
+            self.object(&found, &rpath)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the directories from `/etc/ld.so.conf` followed by the
+    /// default directories.
+    fn system_dirs(&self, class64: bool) -> Vec<PathBuf> {
+        let defaults: &[&str] = if class64 {
+            &["/lib64", "/usr/lib64", "/lib", "/usr/lib"]
+        } else {
+            &["/lib", "/usr/lib"]
+        };
+        let defaults = defaults.iter().map(PathBuf::from);
+        self.search_dirs.iter().cloned().chain(defaults).collect()
+    }
+
+    /// Places the file at `path` within the root, along with the symbolic
+    /// links that lead to it, and returns its path on the host without
+    /// symbolic links.
+    fn place(&self, path: &Path) -> Result<PathBuf, BuildError> {
+        let mut host = PathBuf::from("/");
+        let mut links = 0;
+        let mut pending = vec![path.to_owned()];
+
+        while let Some(path) = pending.pop() {
+            let mut components = path.components();
+            while let Some(component) = components.next() {
+                let name = match component {
+                    Component::RootDir | Component::Prefix(_) => {
+                        host = PathBuf::from("/");
+                        continue;
+                    }
+                    Component::CurDir => continue,
+                    Component::ParentDir => {
+                        host.pop();
+                        continue;
+                    }
+                    Component::Normal(name) => name,
+                };
+
+                let next = host.join(name);
+                let meta =
+                    fs::symlink_metadata(&next).map_err(io_error(&next))?;
+                if !meta.file_type().is_symlink() {
+                    host = next;
+                    continue;
+                }
+
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    let error = io::Error::from_raw_os_error(libc::ELOOP);
+                    return Err(BuildError::Io(path.to_owned(), error));
+                }
+                let target = fs::read_link(&next).map_err(io_error(&next))?;
+                let copy = self.guest(&next)?;
+                match symlink(&target, &copy) {
+                    Err(error)
+                        if error.kind() != io::ErrorKind::AlreadyExists =>
+                    {
+                        return Err(BuildError::Io(copy, error));
+                    }
+                    _ => {}
+                }
+
+                // Resolve the link target, then whatever remains.
+                pending.push(components.as_path().to_owned());
+                pending.push(target);
+                break;
+            }
+        }
+
+        let copy = self.guest(&host)?;
+        if fs::symlink_metadata(&copy).is_err() {
+            self.copy(&host, &copy)?;
+        }
+        Ok(host)
+    }
+
+    /// Returns where the host path `path`, which has no symbolic links, is
+    /// placed within the root, creating its parent directories.
+    fn guest(&self, path: &Path) -> Result<PathBuf, BuildError> {
+        let guest = self.root.join(path.strip_prefix("/").unwrap_or(path));
+        if let Some(parent) = guest.parent() {
+            fs::create_dir_all(parent).map_err(io_error(parent))?;
+        }
+        Ok(guest)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<(), BuildError> {
+        if self.options.hardlink {
+            match fs::hard_link(from, to) {
+                Ok(()) => return Ok(()),
+                // Fall back to copying across filesystems, or when
+                // `fs.protected_hardlinks` forbids linking.
+                Err(error)
+                    if matches!(
+                        error.raw_os_error(),
+                        Some(libc::EXDEV | libc::EPERM | libc::EMLINK)
+                    ) => {}
+                Err(error) => return Err(BuildError::Io(to.to_owned(), error)),
+            }
+        }
+        fs::copy(from, to).map_err(io_error(to))?;
+        Ok(())
+    }
+}
+
+/// Finds `binary` in the host's `PATH` unless it contains a slash.
+fn find_binary(binary: &OsStr) -> Result<PathBuf, BuildError> {
+    let is_executable = |path: &Path| {
+        fs::metadata(path).is_ok_and(|meta| {
+            meta.is_file() && meta.permissions().mode() & 0o111 != 0
+        })
+    };
+
+    if binary.as_bytes().contains(&b'/') {
+        let path = env::current_dir().map_err(io_error("."))?.join(binary);
+        return if is_executable(&path) {
+            Ok(path)
+        } else {
+            Err(BuildError::ProgramNotFound(binary.to_owned()))
+        };
+    }
+
+    let search_path =
+        env::var_os("PATH").unwrap_or_else(|| DEFAULT_PATH.into());
+    env::split_paths(&search_path)
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| dir.join(binary))
+        .find(|path| is_executable(path))
+        .ok_or_else(|| BuildError::ProgramNotFound(binary.to_owned()))
+}
+
+/// Returns the words of the `#!` line of a script, starting with the
+/// interpreter, or nothing if it is not a script.
+fn shebang(path: &Path) -> io::Result<Vec<String>> {
+    let mut line = [0; 256];
+    let mut len = 0;
+    let mut file = File::open(path)?;
+    while len < line.len() {
+        match file.read(&mut line[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+
+    let line = match line[..len].strip_prefix(b"#!") {
+        Some(line) => line,
+        None => return Ok(Vec::new()),
+    };
+    let line = line.split(|&b| b == b'\n').next().unwrap_or_default();
+    Ok(String::from_utf8_lossy(line)
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Expands `$ORIGIN`, `$LIB` and `$PLATFORM` in a `DT_RPATH` or
+/// `DT_RUNPATH` entry.
+fn expand_dir(dir: &str, origin: &Path, class64: bool) -> PathBuf {
+    let lib = if class64 { "lib64" } else { "lib" };
+    let mut expanded = dir.to_owned();
+    for (token, value) in &[
+        ("ORIGIN", origin.to_str().unwrap_or("")),
+        ("LIB", lib),
+        ("PLATFORM", env::consts::ARCH),
+    ] {
+        expanded = expanded
+            .replace(&format!("${{{}}}", token), value)
+            .replace(&format!("${}", token), value);
+    }
+    expanded.into()
+}
+
+/// Returns the directories listed in `/etc/ld.so.conf` and the files it
+/// includes.
+fn ld_so_conf() -> Result<Vec<PathBuf>, BuildError> {
+    let mut dirs = Vec::new();
+    read_ld_so_conf(Path::new("/etc/ld.so.conf"), &mut dirs, 0)?;
+    Ok(dirs)
+}
+
+fn read_ld_so_conf(
+    path: &Path,
+    dirs: &mut Vec<PathBuf>,
+    depth: usize,
+) -> Result<(), BuildError> {
+    let contents = match fs::read_to_string(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        contents => contents.map_err(io_error(path))?,
+    };
+    let base = path.parent().unwrap_or_else(|| Path::new("/"));
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("include") if depth < MAX_SYMLINKS => {
+                for pattern in words {
+                    let pattern = base.join(pattern);
+                    let includes =
+                        glob(&pattern).map_err(io_error(&pattern))?;
+                    for include in includes {
+                        read_ld_so_conf(&include, dirs, depth + 1)?;
+                    }
+                }
+            }
+            Some("include") | Some("hwcap") | None => {}
+            Some(_) => dirs.extend(
+                line.split(|c: char| c == ':' || c == ',' || c.is_whitespace())
+                    .filter(|dir| dir.starts_with('/'))
+                    .map(PathBuf::from),
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the files matching `pattern`, in which only the file name may
+/// contain wildcards, in sorted order.
+fn glob(pattern: &Path) -> io::Result<Vec<PathBuf>> {
+    let name = pattern.file_name().unwrap_or_default().as_bytes();
+    if !name.contains(&b'*') && !name.contains(&b'?') {
+        return Ok(vec![pattern.to_owned()]);
+    }
+
+    let dir = pattern.parent().unwrap_or_else(|| Path::new("/"));
+    let entries = match fs::read_dir(dir) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        }
+        entries => entries?,
+    };
+    let mut matches = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if wildcard_match(name, entry.file_name().as_bytes()) {
+            matches.push(entry.path());
+        }
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+/// Matches `name` against `pattern`, in which `*` matches any sequence of
+/// bytes and `?` any single byte.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => {
+            (0..=name.len()).any(|skip| wildcard_match(rest, &name[skip..]))
+        }
+        Some((&b, rest)) => match name.split_first() {
+            Some((&n, name)) if b == b'?' || b == n => {
+                wildcard_match(rest, name)
+            }
+            _ => false,
+        },
+    }
+}
+
+fn io_error<P: AsRef<Path>>(path: P) -> impl Fn(io::Error) -> BuildError {
+    move |error| BuildError::Io(path.as_ref().to_owned(), error)
+}
+
+/// An error returned when a root directory cannot be built.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// An executable is not in the host's `PATH` or is not executable.
+    ProgramNotFound(OsString),
+    /// An executable is neither an ELF file nor a script.
+    NotExecutable(PathBuf),
+    /// A library cannot be found, along with the file that needs it unless
+    /// it was added with [`RootBuilder::library`].
+    LibraryNotFound(String, Option<PathBuf>),
+    /// A file cannot be read or written.
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ProgramNotFound(program) => {
+                write!(f, "program {:?} not found", program)
+            }
+            Self::NotExecutable(path) => {
+                write!(f, "{:?} is neither an ELF file nor a script", path)
+            }
+            Self::LibraryNotFound(library, None) => {
+                write!(f, "library {:?} not found", library)
+            }
+            Self::LibraryNotFound(library, Some(needed_by)) => write!(
+                f,
+                "library {:?} needed by {:?} not found",
+                library, needed_by
+            ),
+            Self::Io(path, error) => write!(f, "{:?}: {}", path, error),
+        }
+    }
+}
+
+impl error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(_, error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn wildcards_match() {
+        assert!(wildcard_match(b"*.conf", b"libc.conf"));
+        assert!(wildcard_match(b"*.conf", b".conf"));
+        assert!(wildcard_match(b"lib?.conf", b"libc.conf"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(wildcard_match(b"a*b*c", b"axxbyyc"));
+        assert!(!wildcard_match(b"*.conf", b"libc.conf~"));
+        assert!(!wildcard_match(b"lib?.conf", b"lib.conf"));
+        assert!(!wildcard_match(b"", b"a"));
+    }
+
+    #[test]
+    fn ld_so_conf_includes_are_followed() {
+        let dir =
+            env::temp_dir().join(format!("beach-ld-so-conf-{}", process::id()));
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(
+            dir.join("ld.so.conf"),
+            "# comment\n\
+             /usr/local/lib # trailing\n\
+             include conf.d/*.conf missing.conf\n\
+             hwcap 0 nosegneg\n\
+             /opt/a:/opt/b, /opt/c relative\n\
+             include ld.so.conf\n",
+        )
+        .unwrap();
+        fs::write(dir.join("conf.d/b.conf"), "/lib/b\n").unwrap();
+        fs::write(dir.join("conf.d/a.conf"), "/lib/a\n").unwrap();
+        fs::write(dir.join("conf.d/a.conf~"), "/lib/backup\n").unwrap();
+
+        let mut dirs = Vec::new();
+        read_ld_so_conf(&dir.join("ld.so.conf"), &mut dirs, 0).unwrap();
+        let expected = [
+            "/usr/local/lib",
+            "/lib/a",
+            "/lib/b",
+            "/opt/a",
+            "/opt/b",
+            "/opt/c",
+        ];
+        // The file includes itself until the depth limit is reached, where
+        // its includes, including that of conf.d, are ignored.
+        assert_eq!(dirs.len(), MAX_SYMLINKS * expected.len() + 4);
+        assert_eq!(dirs[..expected.len()], expected.map(PathBuf::from));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,487 @@
+use super::{Action, Arch, Cmp, Filter, Rule};
+use crate::Error;
+use std::{collections::BTreeMap, convert::TryFrom, io};
+
+// Instruction classes and fields from `linux/bpf_common.h`.
+const BPF_LD: u16 = 0x00;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_W: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_AND: u16 = 0x50;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_K: u16 = 0x00;
+
+/// The maximum length of a filter, from `linux/bpf_common.h`.
+const BPF_MAXINSNS: usize = 4096;
+
+// Offsets into `struct seccomp_data`.
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+const DATA_ARGS: u32 = 16;
+
+/// System calls with this bit set use the x32 ABI on x86_64.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// A classic BPF instruction, laid out like `struct sock_filter`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Instruction {
+    /// The opcode.
+    pub code: u16,
+    /// The offset to jump to if the condition is true.
+    pub jt: u8,
+    /// The offset to jump to if the condition is false.
+    pub jf: u8,
+    /// The generic field, such as a constant or offset.
+    pub k: u32,
+}
+
+/// A compiled seccomp filter, ready to be installed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+}
+
+impl Program {
+    /// Returns the instructions of the program.
+    #[inline]
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Installs the filter on the calling thread.
+    ///
+    /// Only async-signal-safe operations are used, so this may be called
+    /// between `fork` and `exec`. `PR_SET_NO_NEW_PRIVS` must be set unless
+    /// the caller has `CAP_SYS_ADMIN`.
+    pub(crate) fn install(&self) -> io::Result<()> {
+        let prog = libc::sock_fprog {
+            len: self.instructions.len() as u16,
+            filter: self.instructions.as_ptr() as *mut libc::sock_filter,
+        };
+        let ret = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const libc::sock_fprog,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Label(usize);
+
+/// Where a jump goes.
+#[derive(Clone, Copy)]
+enum Target {
+    Next,
+    Label(Label),
+}
+
+enum Op {
+    Stmt {
+        code: u16,
+        k: u32,
+    },
+    Jump {
+        code: u16,
+        k: u32,
+        jt: Target,
+        jf: Target,
+    },
+}
+
+/// Assembles instructions with symbolic jump targets.
+#[derive(Default)]
+struct Asm {
+    ops: Vec<Op>,
+    labels: Vec<Option<usize>>,
+}
+
+impl Asm {
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.labels[label.0] = Some(self.ops.len());
+    }
+
+    fn load(&mut self, offset: u32) {
+        let code = BPF_LD | BPF_W | BPF_ABS;
+        self.ops.push(Op::Stmt { code, k: offset });
+    }
+
+    fn and(&mut self, k: u32) {
+        let code = BPF_ALU | BPF_AND | BPF_K;
+        self.ops.push(Op::Stmt { code, k });
+    }
+
+    fn ret(&mut self, k: u32) {
+        let code = BPF_RET | BPF_K;
+        self.ops.push(Op::Stmt { code, k });
+    }
+
+    fn jump(&mut self, op: u16, k: u32, jt: Target, jf: Target) {
+        let code = BPF_JMP | op | BPF_K;
+        self.ops.push(Op::Jump { code, k, jt, jf });
+    }
+
+    fn finish(self) -> Result<Program, Error> {
+        if self.ops.len() > BPF_MAXINSNS {
+            return Err(Error::FilterTooLarge);
+        }
+
+        let labels = &self.labels;
+        let offset = |pc: usize, target: Target| match target {
+            Target::Next => Ok(0),
+            Target::Label(label) => {
+                let dest = labels[label.0].expect("unbound label");
+                u8::try_from(dest - pc - 1).map_err(|_| Error::FilterTooLarge)
+            }
+        };
+
+        let mut instructions = Vec::with_capacity(self.ops.len());
+        for (pc, op) in self.ops.iter().enumerate() {
+            instructions.push(match *op {
+                Op::Stmt { code, k } => Instruction {
+                    code,
+                    jt: 0,
+                    jf: 0,
+                    k,
+                },
+                Op::Jump { code, k, jt, jf } => Instruction {
+                    code,
+                    jt: offset(pc, jt)?,
+                    jf: offset(pc, jf)?,
+                    k,
+                },
+            });
+        }
+        Ok(Program { instructions })
+    }
+}
+
+pub(super) fn compile(filter: &Filter, arch: Arch) -> Result<Program, Error> {
+    // Group rules by system call, preserving their order.
+    let mut groups = BTreeMap::<u32, Vec<&Rule>>::new();
+    for rule in &filter.rules {
+        let nr = match arch.syscall(&rule.syscall) {
+            Some(nr) => nr,
+            None if is_known(&rule.syscall) => continue,
+            None => return Err(Error::UnknownSyscall(rule.syscall.clone())),
+        };
+        groups.entry(nr).or_default().push(rule);
+    }
+
+    let mut asm = Asm::default();
+    let kill = Action::KillProcess.ret();
+    let default = filter.default.ret();
+
+    // Reject system calls made through another architecture's ABI.
+    let native = asm.label();
+    asm.load(DATA_ARCH);
+    asm.jump(
+        BPF_JEQ,
+        arch.audit_arch(),
+        Target::Label(native),
+        Target::Next,
+    );
+    asm.ret(kill);
+    asm.bind(native);
+    asm.load(DATA_NR);
+    if arch == Arch::X86_64 {
+        let native_abi = asm.label();
+        let op = BPF_JGE;
+        asm.jump(op, X32_SYSCALL_BIT, Target::Next, Target::Label(native_abi));
+        asm.ret(kill);
+        asm.bind(native_abi);
+    }
+
+    for (nr, rules) in groups {
+        let next_syscall = asm.label();
+        asm.jump(BPF_JEQ, nr, Target::Next, Target::Label(next_syscall));
+
+        let mut matches_all = false;
+        for rule in rules {
+            let next_rule = asm.label();
+            for &(index, cmp) in &rule.args {
+                compare(&mut asm, index, cmp, next_rule);
+            }
+            asm.ret(rule.action.ret());
+            asm.bind(next_rule);
+
+            if rule.args.is_empty() {
+                // Later rules for this system call are unreachable.
+                matches_all = true;
+                break;
+            }
+        }
+        if !matches_all {
+            asm.ret(default);
+        }
+
+        // Argument comparisons clobber the accumulator.
+        asm.bind(next_syscall);
+        asm.load(DATA_NR);
+    }
+
+    asm.ret(default);
+    asm.finish()
+}
+
+/// Returns whether `name` is a system call on any supported architecture.
+fn is_known(name: &str) -> bool {
+    [Arch::X86_64, Arch::Aarch64]
+        .iter()
+        .any(|arch| arch.syscall(name).is_some())
+}
+
+/// Emits code that falls through if argument `index` satisfies `cmp`, or
+/// jumps to `fail` otherwise.
+///
+/// Arguments are 64-bit and little-endian on all supported architectures.
+fn compare(asm: &mut Asm, index: u8, cmp: Cmp, fail: Label) {
+    let lo = DATA_ARGS + 8 * u32::from(index);
+    let hi = lo + 4;
+    let split = |value: u64| ((value >> 32) as u32, value as u32);
+    let fail = Target::Label(fail);
+    let next = Target::Next;
+
+    match cmp {
+        Cmp::Eq(value) => {
+            let (vh, vl) = split(value);
+            asm.load(hi);
+            asm.jump(BPF_JEQ, vh, next, fail);
+            asm.load(lo);
+            asm.jump(BPF_JEQ, vl, next, fail);
+        }
+        Cmp::MaskedEq { mask, value } => {
+            let (mh, ml) = split(mask);
+            let (vh, vl) = split(value & mask);
+            asm.load(hi);
+            asm.and(mh);
+            asm.jump(BPF_JEQ, vh, next, fail);
+            asm.load(lo);
+            asm.and(ml);
+            asm.jump(BPF_JEQ, vl, next, fail);
+        }
+        Cmp::Ne(value) => {
+            let (vh, vl) = split(value);
+            let ok = asm.label();
+            asm.load(hi);
+            asm.jump(BPF_JEQ, vh, next, Target::Label(ok));
+            asm.load(lo);
+            asm.jump(BPF_JEQ, vl, fail, next);
+            asm.bind(ok);
+        }
+        Cmp::Gt(value) | Cmp::Ge(value) => {
+            // Compare the upper halves, then the lower halves if equal.
+            let (vh, vl) = split(value);
+            let ok = asm.label();
+            let op = if let Cmp::Gt(_) = cmp {
+                BPF_JGT
+            } else {
+                BPF_JGE
+            };
+            asm.load(hi);
+            asm.jump(BPF_JGT, vh, Target::Label(ok), next);
+            asm.jump(BPF_JEQ, vh, next, fail);
+            asm.load(lo);
+            asm.jump(op, vl, next, fail);
+            asm.bind(ok);
+        }
+        Cmp::Lt(value) | Cmp::Le(value) => {
+            // The negations of `Ge` and `Gt`, respectively.
+            let (vh, vl) = split(value);
+            let ok = asm.label();
+            let op = if let Cmp::Lt(_) = cmp {
+                BPF_JGE
+            } else {
+                BPF_JGT
+            };
+            asm.load(hi);
+            asm.jump(BPF_JGT, vh, fail, next);
+            asm.jump(BPF_JEQ, vh, next, Target::Label(ok));
+            asm.load(lo);
+            asm.jump(op, vl, fail, next);
+            asm.bind(ok);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READ: u32 = 0;
+
+    /// Whether an argument satisfies a comparison with a value.
+    type Holds = fn(u64, u64) -> bool;
+
+    /// Runs `program` as the kernel would for system call `nr` of `arch`
+    /// with `args`, returning the action.
+    fn run(program: &Program, arch: u32, nr: u32, args: [u64; 6]) -> u32 {
+        let load = |offset: u32| match offset {
+            DATA_NR => nr,
+            DATA_ARCH => arch,
+            _ => {
+                let arg = args[((offset - DATA_ARGS) / 8) as usize];
+                (arg >> (8 * (offset % 8))) as u32
+            }
+        };
+
+        let instructions = program.instructions();
+        let (mut pc, mut acc) = (0, 0);
+        loop {
+            let Instruction { code, jt, jf, k } = instructions[pc];
+            pc += 1;
+            let taken = match code {
+                c if c == BPF_RET | BPF_K => return k,
+                c if c == BPF_LD | BPF_W | BPF_ABS => {
+                    acc = load(k);
+                    continue;
+                }
+                c if c == BPF_ALU | BPF_AND | BPF_K => {
+                    acc &= k;
+                    continue;
+                }
+                c if c == BPF_JMP | BPF_JEQ | BPF_K => acc == k,
+                c if c == BPF_JMP | BPF_JGT | BPF_K => acc > k,
+                c if c == BPF_JMP | BPF_JGE | BPF_K => acc >= k,
+                c => panic!("unexpected opcode {:#x}", c),
+            };
+            pc += usize::from(if taken { jt } else { jf });
+        }
+    }
+
+    fn read_with(program: &Program, arg: u64) -> u32 {
+        run(
+            program,
+            Arch::X86_64.audit_arch(),
+            READ,
+            [arg, 0, 0, 0, 0, 0],
+        )
+    }
+
+    #[test]
+    fn comparisons_cover_both_halves() {
+        let values = [
+            0,
+            1,
+            0xffff_fffe,
+            0xffff_ffff,
+            0x1_0000_0000,
+            0x1_0000_0001,
+            0x1_ffff_ffff,
+            u64::MAX - 1,
+            u64::MAX,
+        ];
+        let allow = Action::Allow.ret();
+        let deny = Action::Errno(1).ret();
+
+        for &value in &values {
+            let cmps: [(Cmp, Holds); 7] = [
+                (Cmp::Eq(value), |a, v| a == v),
+                (Cmp::Ne(value), |a, v| a != v),
+                (Cmp::Lt(value), |a, v| a < v),
+                (Cmp::Le(value), |a, v| a <= v),
+                (Cmp::Gt(value), |a, v| a > v),
+                (Cmp::Ge(value), |a, v| a >= v),
+                (
+                    Cmp::MaskedEq {
+                        mask: 0xf_0000_000f,
+                        value,
+                    },
+                    |a, v| a & 0xf_0000_000f == v & 0xf_0000_000f,
+                ),
+            ];
+            for &(cmp, expected) in &cmps {
+                let program = Filter::new(Action::Errno(1))
+                    .rule(Rule::new("read", Action::Allow).arg(0, cmp))
+                    .compile(Arch::X86_64)
+                    .unwrap();
+                for &arg in &values {
+                    let want = if expected(arg, value) { allow } else { deny };
+                    assert_eq!(
+                        read_with(&program, arg),
+                        want,
+                        "{:?} against {:#x}",
+                        cmp,
+                        arg
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rules_are_tried_in_order() {
+        let program = Filter::new(Action::Allow)
+            .rule(Rule::new("read", Action::Errno(1)).arg(0, Cmp::Eq(1)))
+            .rule(Rule::new("write", Action::Errno(3)))
+            .rule(Rule::new("read", Action::Errno(2)).arg(1, Cmp::Eq(0)))
+            .compile(Arch::X86_64)
+            .unwrap();
+        let arch = Arch::X86_64.audit_arch();
+
+        assert_eq!(run(&program, arch, READ, [1, 0, 0, 0, 0, 0]), 0x5_0001);
+        assert_eq!(run(&program, arch, READ, [0, 0, 0, 0, 0, 0]), 0x5_0002);
+        assert_eq!(run(&program, arch, READ, [0, 1, 0, 0, 0, 0]), 0x7fff_0000);
+        assert_eq!(run(&program, arch, 1, [0; 6]), 0x5_0003);
+        assert_eq!(run(&program, arch, 2, [0; 6]), 0x7fff_0000);
+    }
+
+    #[test]
+    fn other_abis_are_killed() {
+        let kill = Action::KillProcess.ret();
+        let filter = Filter::new(Action::Allow);
+
+        let program = filter.compile(Arch::X86_64).unwrap();
+        let x86_64 = Arch::X86_64.audit_arch();
+        assert_eq!(run(&program, x86_64, READ, [0; 6]), Action::Allow.ret());
+        assert_eq!(run(&program, x86_64, X32_SYSCALL_BIT | READ, [0; 6]), kill);
+        let aarch64 = Arch::Aarch64.audit_arch();
+        assert_eq!(run(&program, aarch64, READ, [0; 6]), kill);
+        // 32-bit x86, whose system calls are numbered differently.
+        assert_eq!(run(&program, 0x4000_0003, READ, [0; 6]), kill);
+
+        // The x32 bit has no meaning on other architectures.
+        let program = filter.compile(Arch::Aarch64).unwrap();
+        let nr = X32_SYSCALL_BIT | READ;
+        assert_eq!(run(&program, aarch64, nr, [0; 6]), Action::Allow.ret());
+        assert_eq!(run(&program, x86_64, READ, [0; 6]), kill);
+    }
+
+    #[test]
+    fn syscalls_of_other_architectures_are_skipped() {
+        // `open` does not exist on aarch64.
+        let filter = Filter::new(Action::Allow).errno("open", 1);
+        let program = filter.compile(Arch::Aarch64).unwrap();
+        assert_eq!(
+            program,
+            Filter::new(Action::Allow).compile(Arch::Aarch64).unwrap()
+        );
+        assert!(filter.compile(Arch::X86_64).unwrap() != program);
+
+        match Filter::new(Action::Allow)
+            .allow("no_such_call")
+            .compile(Arch::X86_64)
+        {
+            Err(Error::UnknownSyscall(name)) => {
+                assert_eq!(name, "no_such_call")
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
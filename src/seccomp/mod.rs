@@ -0,0 +1,314 @@
+//! Restricting system calls with
+//! [seccomp-BPF](https://www.kernel.org/doc/html/latest/userspace-api/seccomp_filter.html).
+//!
+//! A [`Filter`] is a list of [`Rule`]s that is compiled to classic BPF for a
+//! given [`Arch`] and installed with [`Chroot::seccomp`].
+//!
+//! # Examples
+//!
+//! ```
+//! # return;
+//! use beach::{seccomp::{Action, Cmp, Filter, Rule}, Chroot};
+//!
+//! let filter = Filter::default_profile()
+//!     .errno("socket", libc::EACCES)
+//!     .rule(Rule::new("personality", Action::Allow).arg(0, Cmp::Eq(0)));
+//!
+//! Chroot::new()
+//!     .seccomp(filter)
+//!     .command("/path/to/root", "make")
+//!     .spawn();
+//! ```
+//!
+//! [`Chroot::seccomp`]: crate::Chroot::seccomp
+
+mod bpf;
+mod syscalls;
+
+pub use self::bpf::{Instruction, Program};
+
+use crate::Error;
+
+/// What happens when a system call matches a [`Rule`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// The system call is executed.
+    Allow,
+    /// The system call fails with the given `errno`.
+    Errno(u16),
+    /// The system call is executed and logged by the kernel.
+    Log,
+    /// The thread receives `SIGSYS`.
+    Trap,
+    /// The thread is killed.
+    KillThread,
+    /// The whole process is killed.
+    KillProcess,
+}
+
+impl Action {
+    pub(crate) fn ret(self) -> u32 {
+        match self {
+            Self::Allow => 0x7fff_0000,
+            Self::Errno(errno) => 0x0005_0000 | u32::from(errno),
+            Self::Log => 0x7ffc_0000,
+            Self::Trap => 0x0003_0000,
+            Self::KillThread => 0x0000_0000,
+            Self::KillProcess => 0x8000_0000,
+        }
+    }
+}
+
+/// An architecture that filters can be compiled for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Arch {
+    /// 64-bit x86.
+    X86_64,
+    /// 64-bit ARM.
+    Aarch64,
+}
+
+impl Arch {
+    /// Returns the architecture of the current target, if supported.
+    pub fn native() -> Option<Self> {
+        if cfg!(target_arch = "x86_64") {
+            Some(Self::X86_64)
+        } else if cfg!(target_arch = "aarch64") {
+            Some(Self::Aarch64)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of the system call named `name`.
+    pub fn syscall(self, name: &str) -> Option<u32> {
+        let table = self.syscalls();
+        let index = table.binary_search_by_key(&name, |&(n, _)| n).ok()?;
+        Some(table[index].1)
+    }
+
+    fn syscalls(self) -> &'static [(&'static str, u32)] {
+        match self {
+            Self::X86_64 => syscalls::X86_64,
+            Self::Aarch64 => syscalls::AARCH64,
+        }
+    }
+
+    /// The `AUDIT_ARCH_*` value in `seccomp_data.arch`.
+    pub(crate) fn audit_arch(self) -> u32 {
+        match self {
+            Self::X86_64 => 0xc000_003e,
+            Self::Aarch64 => 0xc000_00b7,
+        }
+    }
+}
+
+/// A comparison against a 64-bit system call argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Cmp {
+    /// The argument equals the value.
+    Eq(u64),
+    /// The argument does not equal the value.
+    Ne(u64),
+    /// The argument is less than the value.
+    Lt(u64),
+    /// The argument is less than or equal to the value.
+    Le(u64),
+    /// The argument is greater than the value.
+    Gt(u64),
+    /// The argument is greater than or equal to the value.
+    Ge(u64),
+    /// The argument masked with `mask` equals `value`.
+    MaskedEq {
+        /// The bits of the argument to compare.
+        mask: u64,
+        /// The expected value of those bits.
+        value: u64,
+    },
+}
+
+/// Applies an [`Action`] to a system call, optionally only when its
+/// arguments match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    syscall: String,
+    action: Action,
+    args: Vec<(u8, Cmp)>,
+}
+
+impl Rule {
+    /// Creates a rule that applies `action` to the system call named
+    /// `syscall`.
+    pub fn new<S: Into<String>>(syscall: S, action: Action) -> Self {
+        Self {
+            syscall: syscall.into(),
+            action,
+            args: Vec::new(),
+        }
+    }
+
+    /// Only match when argument `index` satisfies `cmp`.
+    ///
+    /// All argument comparisons of a rule must match.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than 6, the number of system call
+    /// arguments.
+    pub fn arg(mut self, index: u8, cmp: Cmp) -> Self {
+        assert!(index < 6, "system call argument index out of range");
+        self.args.push((index, cmp));
+        self
+    }
+}
+
+/// A seccomp filter, consisting of [`Rule`]s and an [`Action`] for system
+/// calls that no rule matches.
+///
+/// For each system call, rules are checked in the order they were added and
+/// the first match applies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    default: Action,
+    rules: Vec<Rule>,
+}
+
+/// Denied by [`Filter::default_profile`] with `EPERM`, as they affect the
+/// host or escape the sandbox.
+const DENIED: &[&str] = &[
+    "acct",
+    "add_key",
+    "adjtimex",
+    "bpf",
+    "clock_adjtime",
+    "clock_settime",
+    "create_module",
+    "delete_module",
+    "finit_module",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "fspick",
+    "get_kernel_syms",
+    "init_module",
+    "ioperm",
+    "iopl",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "mount",
+    "mount_setattr",
+    "move_mount",
+    "name_to_handle_at",
+    "nfsservctl",
+    "open_by_handle_at",
+    "open_tree",
+    "perf_event_open",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "query_module",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "setdomainname",
+    "sethostname",
+    "setns",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "syslog",
+    "umount2",
+    "unshare",
+    "uselib",
+    "userfaultfd",
+    "vhangup",
+];
+
+impl Filter {
+    /// Creates a filter without rules that applies `default` to every system
+    /// call.
+    pub fn new(default: Action) -> Self {
+        Self {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Returns a filter suitable for running untrusted build scripts.
+    ///
+    /// System calls are allowed by default, except for those that
+    /// administer the system, load kernel code, inspect other processes or
+    /// create namespaces, which fail with `EPERM`. Injecting terminal input
+    /// with `TIOCSTI` is denied, and `clone3` fails with `ENOSYS` since its
+    /// flags cannot be inspected, making the C library fall back to `clone`.
+    pub fn default_profile() -> Self {
+        let eperm = Action::Errno(libc::EPERM as u16);
+        let namespaces = (libc::CLONE_NEWNS
+            | libc::CLONE_NEWUTS
+            | libc::CLONE_NEWIPC
+            | libc::CLONE_NEWUSER
+            | libc::CLONE_NEWPID
+            | libc::CLONE_NEWNET
+            | libc::CLONE_NEWCGROUP) as u64;
+
+        // The type of `ioctl` requests varies by target.
+        #[allow(clippy::unnecessary_cast)]
+        let tiocsti = libc::TIOCSTI as u64;
+
+        let mut filter = Self::new(Action::Allow);
+        for syscall in DENIED {
+            filter = filter.rule(Rule::new(*syscall, eperm));
+        }
+        filter
+            .rule(Rule::new("clone", Action::Allow).arg(
+                0,
+                Cmp::MaskedEq {
+                    mask: namespaces,
+                    value: 0,
+                },
+            ))
+            .rule(Rule::new("clone", eperm))
+            .errno("clone3", libc::ENOSYS)
+            .rule(Rule::new("ioctl", eperm).arg(
+                1,
+                // The kernel ignores the upper bits of the request.
+                Cmp::MaskedEq {
+                    mask: 0xffff_ffff,
+                    value: tiocsti,
+                },
+            ))
+    }
+
+    /// Adds `rule` to the filter.
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Allows the system call named `syscall`.
+    pub fn allow<S: Into<String>>(self, syscall: S) -> Self {
+        self.rule(Rule::new(syscall, Action::Allow))
+    }
+
+    /// Makes the system call named `syscall` fail with `errno`.
+    pub fn errno<S: Into<String>>(self, syscall: S, errno: i32) -> Self {
+        self.rule(Rule::new(syscall, Action::Errno(errno as u16)))
+    }
+
+    /// Kills the process when it makes the system call named `syscall`.
+    pub fn kill<S: Into<String>>(self, syscall: S) -> Self {
+        self.rule(Rule::new(syscall, Action::KillProcess))
+    }
+
+    /// Compiles the filter to classic BPF for `arch`.
+    ///
+    /// Rules for system calls that exist on another supported architecture
+    /// but not on `arch` are skipped. Calls made through a different
+    /// architecture's ABI kill the process.
+    pub fn compile(&self, arch: Arch) -> Result<Program, Error> {
+        bpf::compile(self, arch)
+    }
+}
@@ -0,0 +1,378 @@
+use crate::{
+    caps::{self, Capability, Caps},
+    child::{self, Progress},
+    fd::Fds,
+    ids::Ids,
+    init, landlock,
+    mount::{self, Step},
+    net::{self, Network},
+    rlimit::Rlimit,
+    seccomp::Program,
+};
+use std::{convert::TryFrom, ffi::CString, fmt, io};
+
+/// Everything the child needs to enter the root, prepared in the parent so
+/// that the `pre_exec` hook does not allocate.
+#[derive(Debug)]
+pub(crate) struct Setup {
+    pub root: CString,
+    /// Whether to join the cgroup created by [`child::spawn`].
+    pub cgroup: bool,
+    pub fds: Fds,
+    /// The working directory within the root, or `None` to keep the current
+    /// one.
+    pub current_dir: Option<CString>,
+    pub ids: Ids,
+    pub user_namespace: Option<IdMaps>,
+    pub mounts: Vec<Step>,
+    /// Whether to enter `root` with `pivot_root(2)` instead of `chroot(2)`.
+    pub pivot_root: bool,
+    pub caps: Caps,
+    pub no_new_privs: bool,
+    pub rlimits: Vec<Rlimit>,
+    pub network: Network,
+    /// Whether to run the program in a new PID namespace under an init.
+    pub pid_namespace: bool,
+    pub landlock: Option<landlock::Prepared>,
+    pub hostname: Option<Vec<u8>>,
+    pub domainname: Option<Vec<u8>>,
+    pub ipc_namespace: bool,
+    /// The contents of `/proc/self/timens_offsets` for a new time namespace.
+    pub time_offsets: Option<Vec<u8>>,
+    pub seccomp: Option<Program>,
+}
+
+/// The contents of `/proc/self/uid_map` and `/proc/self/gid_map` for a new
+/// user namespace.
+#[derive(Debug)]
+pub(crate) struct IdMaps {
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+}
+
+impl IdMaps {
+    /// Maps a single user and group within the namespace to ones outside.
+    pub fn single(
+        inner_uid: libc::uid_t,
+        outer_uid: libc::uid_t,
+        inner_gid: libc::gid_t,
+        outer_gid: libc::gid_t,
+    ) -> Self {
+        Self {
+            uid_map: format!("{} {} 1\n", inner_uid, outer_uid).into_bytes(),
+            gid_map: format!("{} {} 1\n", inner_gid, outer_gid).into_bytes(),
+        }
+    }
+}
+
+/// A step of setting up the sandbox within the child, reported by
+/// [`Error::Setup`](crate::Error::Setup) when it fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Stage {
+    /// Joining the [`Cgroup`](crate::Cgroup).
+    Cgroup,
+    /// Arranging the descriptors passed with
+    /// [`Chroot::pass_fd`](crate::Chroot::pass_fd).
+    Fds,
+    /// Creating the user namespace and mapping IDs.
+    UserNamespace,
+    /// Creating the mount, network, UTS, IPC or time namespace.
+    Namespaces,
+    /// Bringing up the loopback interface.
+    Network,
+    /// Setting the hostname, domain name or time offsets.
+    Identity,
+    /// Creating the PID namespace and its init.
+    PidNamespace,
+    /// Mounting within the root directory.
+    Mount,
+    /// Changing the root directory.
+    Root,
+    /// Changing the working directory.
+    CurrentDir,
+    /// Setting resource limits.
+    Rlimit,
+    /// Setting capabilities or `no_new_privs`.
+    Capabilities,
+    /// Changing the user and groups.
+    Credentials,
+    /// Applying the Landlock ruleset.
+    Landlock,
+    /// Installing the seccomp filter.
+    Seccomp,
+    /// Executing the program.
+    Exec,
+}
+
+impl Stage {
+    const ALL: &'static [Self] = &[
+        Self::Cgroup,
+        Self::Fds,
+        Self::UserNamespace,
+        Self::Namespaces,
+        Self::Network,
+        Self::Identity,
+        Self::PidNamespace,
+        Self::Mount,
+        Self::Root,
+        Self::CurrentDir,
+        Self::Rlimit,
+        Self::Capabilities,
+        Self::Credentials,
+        Self::Landlock,
+        Self::Seccomp,
+        Self::Exec,
+    ];
+
+    pub(crate) fn from_raw(raw: i32) -> Option<Self> {
+        Self::ALL.get(usize::try_from(raw).ok()?).copied()
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Cgroup => "joining the cgroup",
+            Self::Fds => "passing file descriptors",
+            Self::UserNamespace => "creating the user namespace",
+            Self::Namespaces => "creating namespaces",
+            Self::Network => "configuring the network",
+            Self::Identity => "setting the hostname or clocks",
+            Self::PidNamespace => "creating the PID namespace",
+            Self::Mount => "mounting",
+            Self::Root => "changing the root directory",
+            Self::CurrentDir => "changing the working directory",
+            Self::Rlimit => "setting resource limits",
+            Self::Capabilities => "setting capabilities",
+            Self::Credentials => "changing user and groups",
+            Self::Landlock => "applying the Landlock ruleset",
+            Self::Seccomp => "installing the seccomp filter",
+            Self::Exec => "executing the program",
+        })
+    }
+}
+
+impl Setup {
+    fn needs_mount_namespace(&self) -> bool {
+        self.pivot_root || !self.mounts.is_empty()
+    }
+
+    /// Runs in the child between `fork` and `exec`.
+    ///
+    /// Only async-signal-safe operations may be performed here. `progress`
+    /// is updated before each step so that failures can be attributed.
+    pub fn run(&mut self, progress: &mut Progress) -> io::Result<()> {
+        let ids = &self.ids;
+
+        // Joined first so that everything the child does is accounted for.
+        if self.cgroup {
+            child::join_cgroup(progress)?;
+        }
+
+        progress.stage(Stage::Fds);
+        self.fds.apply()?;
+
+        progress.stage(Stage::UserNamespace);
+        if let Some(maps) = &self.user_namespace {
+            unsafe {
+                cvt(libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNS))?;
+            }
+
+            // Unprivileged processes must give up `setgroups(2)` before
+            // writing a group mapping. Kernels before 3.19 lack this file.
+            progress.path(b"/proc/self/setgroups\0");
+            match write_file(b"/proc/self/setgroups\0", b"deny") {
+                Err(error) if error.raw_os_error() == Some(libc::ENOENT) => {}
+                result => result?,
+            }
+            progress.path(b"/proc/self/uid_map\0");
+            write_file(b"/proc/self/uid_map\0", &maps.uid_map)?;
+            progress.path(b"/proc/self/gid_map\0");
+            write_file(b"/proc/self/gid_map\0", &maps.gid_map)?;
+        } else if self.needs_mount_namespace() {
+            progress.stage(Stage::Namespaces);
+            unsafe {
+                cvt(libc::unshare(libc::CLONE_NEWNS))?;
+            }
+        }
+
+        // Created after the user namespace so that it is owned by it.
+        if self.network != Network::Host {
+            progress.stage(Stage::Namespaces);
+            unsafe {
+                cvt(libc::unshare(libc::CLONE_NEWNET))?;
+            }
+            if self.network == Network::Loopback {
+                progress.stage(Stage::Network);
+                net::loopback_up()?;
+            }
+        }
+
+        let mut namespaces = 0;
+        if self.hostname.is_some() || self.domainname.is_some() {
+            namespaces |= libc::CLONE_NEWUTS;
+        }
+        if self.ipc_namespace {
+            namespaces |= libc::CLONE_NEWIPC;
+        }
+        if self.time_offsets.is_some() {
+            namespaces |= libc::CLONE_NEWTIME;
+        }
+        if namespaces != 0 {
+            progress.stage(Stage::Namespaces);
+            unsafe {
+                cvt(libc::unshare(namespaces))?;
+            }
+        }
+
+        progress.stage(Stage::Identity);
+        unsafe {
+            if let Some(name) = &self.hostname {
+                cvt(libc::sethostname(name.as_ptr().cast(), name.len()))?;
+            }
+            if let Some(name) = &self.domainname {
+                cvt(libc::setdomainname(name.as_ptr().cast(), name.len()))?;
+            }
+        }
+
+        // Offsets must be set before any process enters the namespace,
+        // which happens on `exec` or `fork`.
+        if let Some(offsets) = &self.time_offsets {
+            progress.path(b"/proc/self/timens_offsets\0");
+            write_file(b"/proc/self/timens_offsets\0", offsets)?;
+        }
+
+        // Only children enter the namespace, so the rest of the setup runs
+        // in the init. It must precede mounting `/proc`, which shows the PID
+        // namespace of the process that mounts it.
+        if self.pid_namespace {
+            progress.stage(Stage::PidNamespace);
+            init::enter_namespace()?;
+        }
+
+        progress.stage(Stage::Mount);
+        if self.needs_mount_namespace() {
+            mount::make_private()?;
+        }
+
+        if self.pivot_root {
+            // `pivot_root(2)` requires the new root to be a mount point.
+            progress.path(self.root.as_bytes_with_nul());
+            mount::bind_self(&self.root)?;
+        }
+
+        for mount in &self.mounts {
+            mount.run(progress)?;
+        }
+
+        unsafe {
+            progress.stage(Stage::Root);
+            progress.path(self.root.as_bytes_with_nul());
+            if self.pivot_root {
+                // Stack the old root on top of the new one, then detach it.
+                let dot = b".\0".as_ptr().cast::<libc::c_char>();
+                cvt(libc::chdir(self.root.as_ptr()))?;
+                cvt(libc::syscall(libc::SYS_pivot_root, dot, dot) as _)?;
+                cvt(libc::umount2(dot, libc::MNT_DETACH))?;
+            } else {
+                cvt(libc::chroot(self.root.as_ptr()))?;
+            }
+
+            progress.stage(Stage::CurrentDir);
+            if let Some(dir) = &self.current_dir {
+                progress.path(dir.as_bytes_with_nul());
+                cvt(libc::chdir(dir.as_ptr()))?;
+            }
+
+            // Hard limits can only be raised with `CAP_SYS_RESOURCE`.
+            progress.stage(Stage::Rlimit);
+            for limit in &self.rlimits {
+                progress.resource(limit.resource);
+                limit.apply()?;
+            }
+
+            // The bounding set can only be reduced with `CAP_SETPCAP`.
+            progress.stage(Stage::Capabilities);
+            self.caps.apply_before_setuid()?;
+
+            // Supplementary groups and the group must be changed while we
+            // still have the privileges to do so.
+            progress.stage(Stage::Credentials);
+            if let Some(groups) = &ids.groups {
+                cvt(libc::setgroups(groups.len() as _, groups.as_ptr()))?;
+            }
+            if let Some(gid) = ids.gid {
+                cvt(libc::setgid(gid))?;
+            }
+            if let Some(uid) = ids.uid {
+                cvt(libc::setuid(uid))?;
+            }
+        }
+
+        progress.stage(Stage::Capabilities);
+        self.caps.apply_after_setuid()?;
+
+        if self.no_new_privs {
+            unsafe {
+                cvt(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            }
+        }
+
+        progress.stage(Stage::Landlock);
+        if let Some(landlock) = &self.landlock {
+            landlock.apply()?;
+        }
+
+        progress.stage(Stage::PidNamespace);
+        if self.pid_namespace {
+            init::kill_with_parent()?;
+        }
+
+        // Installed last so that it does not restrict the setup itself.
+        progress.stage(Stage::Seccomp);
+        if let Some(seccomp) = &self.seccomp {
+            if !self.no_new_privs && !caps::has_effective(Capability::SysAdmin)?
+            {
+                unsafe {
+                    cvt(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+                }
+            }
+            seccomp.install()?;
+        }
+
+        if self.pid_namespace {
+            progress.stage(Stage::PidNamespace);
+            init::fork_program()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts a `-1` return value into the current `errno`.
+pub(crate) fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Writes `data` to the file at the nul-terminated `path` with a single
+/// `write(2)`, as required for `/proc` files.
+pub(crate) fn write_file(path: &[u8], data: &[u8]) -> io::Result<()> {
+    unsafe {
+        let fd = cvt(libc::open(
+            path.as_ptr().cast(),
+            libc::O_WRONLY | libc::O_CLOEXEC,
+        ))?;
+        let ret = libc::write(fd, data.as_ptr().cast(), data.len());
+        let error = io::Error::last_os_error();
+        libc::close(fd);
+        if ret == -1 {
+            return Err(error);
+        }
+    }
+    Ok(())
+}